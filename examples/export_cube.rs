@@ -0,0 +1,41 @@
+//! Export a bellman circuit proving knowledge of x such that x^3 + x + 5 = out.
+//!
+//!     cargo run --example export_cube -- --target prover local/cube
+
+use bellman::{Circuit, ConstraintSystem, SynthesisError, gadgets::num::AllocatedNum};
+use bls12_381::Scalar;
+use zkinterface_bellman::export::export_main;
+
+
+struct CubeCircuit {
+    x: Option<Scalar>,
+}
+
+impl Circuit<Scalar> for CubeCircuit {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let x = AllocatedNum::alloc(cs.namespace(|| "x"), || {
+            self.x.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let x2 = x.square(cs.namespace(|| "x2"))?;
+        let x3 = x2.mul(cs.namespace(|| "x3"), &x)?;
+
+        let out = AllocatedNum::alloc(cs.namespace(|| "out"), || {
+            let x = x.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+            let x3 = x3.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+            Ok(x3 + x + Scalar::from(5))
+        })?;
+        out.inputize(cs.namespace(|| "out input"))?;
+
+        cs.enforce(
+            || "out = x3 + x + 5",
+            |lc| lc + x3.get_variable() + x.get_variable() + (Scalar::from(5), CS::one()),
+            |lc| lc + CS::one(),
+            |lc| lc + out.get_variable(),
+        );
+        Ok(())
+    }
+}
+
+fn main() -> zkinterface::Result<()> {
+    export_main(CubeCircuit { x: Some(Scalar::from(3)) })
+}
@@ -0,0 +1,312 @@
+//! Prove many witnesses of the same circuit, loading the proving key once.
+//!
+//! The constraints are given once, in the circuit messages. Each witness workspace holds a circuit header
+//! with the instance values and a witness, like the workspace of a single proof; its constraints, if any,
+//! are ignored. The proof of each witness is written into its workspace, and a manifest listing all proofs
+//! into the workspace of the key.
+
+use std::fs::File;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::io::Write;
+use bellman::groth16::ParameterSource;
+use bls12_381::{Bls12, Scalar};
+use zkinterface::{CircuitHeader, ConstraintSystem, Reader, Result, Witness};
+use crate::compression::read_workspace;
+use crate::import::try_read_scalar;
+use crate::parameters::{KeyOptions, MmapParameters, read_parameters};
+use crate::zkif_backend::{DEFAULT_KEY_PATH, DEFAULT_PROOF_PATH, prove_circuit};
+
+pub const DEFAULT_MANIFEST_PATH: &str = "bellman-batch.json";
+
+/// Options of prove_batch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BatchOptions {
+    /// How many proofs to create at the same time. Each proof also uses the worker threads of bellman.
+    pub threads: usize,
+    pub key_options: KeyOptions,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions { threads: 1, key_options: KeyOptions::default() }
+    }
+}
+
+/// The outcome of proving one witness: the path of the proof, or why it failed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchResult {
+    pub witness: PathBuf,
+    pub proof: std::result::Result<PathBuf, String>,
+}
+
+/// Prove each witness workspace with the constraints of the circuit and the key in the workspace.
+/// A failed witness does not stop the others; the results are in the order of the witnesses.
+pub fn prove_batch(
+    circuit: &Reader,
+    workspace: &Path,
+    witnesses: &[PathBuf],
+    options: &BatchOptions,
+) -> Result<Vec<BatchResult>> {
+    let mut constraints = vec![];
+    for msg in circuit {
+        if let Some(cs) = msg.message_as_constraint_system() {
+            let mut buf = vec![];
+            ConstraintSystem::from(cs).write_into(&mut buf)?;
+            constraints.push(buf);
+        }
+    }
+    if constraints.is_empty() {
+        return Err("The circuit has no constraints.".into());
+    }
+    let variables = header_variables(circuit).ok_or("The circuit has no header.")?;
+    for constraint in circuit.iter_constraints() {
+        for term in constraint.a.iter().chain(&constraint.b).chain(&constraint.c) {
+            try_read_scalar::<Scalar>(term.value)?;
+        }
+    }
+
+    let key_path = workspace.join(DEFAULT_KEY_PATH);
+    eprintln!("Reading parameters from {}", key_path.display());
+    let key_options = options.key_options;
+    if key_options.mmap {
+        let params = MmapParameters::<Bls12>::open(&key_path, key_options.checked)?;
+        run_batch(Arc::new(params), constraints, variables, witnesses, options)
+    } else {
+        let params = read_parameters::<Bls12>(&key_path, key_options.checked)?;
+        run_batch(Arc::new(params), constraints, variables, witnesses, options)
+    }
+}
+
+fn run_batch<P>(
+    params: Arc<P>,
+    constraints: Vec<Vec<u8>>,
+    variables: (Vec<u64>, u64),
+    witnesses: &[PathBuf],
+    options: &BatchOptions,
+) -> Result<Vec<BatchResult>>
+    where P: Send + Sync + 'static, for<'p> &'p P: ParameterSource<Bls12>
+{
+    let constraints = Arc::new(constraints);
+    let variables = Arc::new(variables);
+    let witnesses = Arc::new(witnesses.to_vec());
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(vec![None; witnesses.len()]));
+
+    let workers: Vec<_> = (0..options.threads.max(1).min(witnesses.len())).map(|_| {
+        let (params, constraints, variables, witnesses, next, results) = (
+            params.clone(), constraints.clone(), variables.clone(), witnesses.clone(), next.clone(), results.clone());
+        let key_options = options.key_options;
+
+        thread::spawn(move || loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            let witness = match witnesses.get(i) {
+                Some(witness) => witness,
+                None => break,
+            };
+            // A panic while proving one witness fails that witness only.
+            let proof = catch_unwind(AssertUnwindSafe(|| prove_witness(&*params, &constraints, &variables, witness, &key_options)))
+                .unwrap_or_else(|_| Err("The prover panicked.".into()))
+                .map_err(|err| err.to_string());
+            match &proof {
+                Ok(path) => eprintln!("Written proof into {}", path.display()),
+                Err(err) => eprintln!("Cannot prove {}: {}", witness.display(), err),
+            }
+            results.lock().unwrap()[i] = Some(BatchResult { witness: witness.clone(), proof });
+        })
+    }).collect();
+
+    for worker in workers {
+        worker.join().map_err(|_| "A proving thread panicked.")?;
+    }
+
+    let results = results.lock().unwrap().drain(..).map(Option::unwrap).collect();
+    Ok(results)
+}
+
+fn prove_witness<P: ParameterSource<Bls12>>(
+    params: P,
+    constraints: &[Vec<u8>],
+    variables: &(Vec<u64>, u64),
+    witness: &Path,
+    key_options: &KeyOptions,
+) -> Result<PathBuf> {
+    let workspace = read_workspace(&[witness.to_path_buf()])?;
+
+    let mut reader = Reader::new();
+    for msg in &workspace {
+        // Keep the header and the witness only.
+        let mut buf = vec![];
+        if let Some(header) = msg.message_as_circuit_header() {
+            CircuitHeader::from(header).write_into(&mut buf)?;
+        } else if let Some(witness) = msg.message_as_witness() {
+            Witness::from(witness).write_into(&mut buf)?;
+        } else {
+            continue;
+        }
+        reader.push_message(buf)?;
+    }
+    match header_variables(&reader) {
+        None => return Err("No circuit header in the witness workspace.".into()),
+        Some(ref found) if found != variables =>
+            return Err("The instance variables of the witness workspace do not match the circuit.".into()),
+        _ => {}
+    }
+    let instance_vars = reader.instance_variables().unwrap_or_default();
+    for var in instance_vars.into_iter().chain(reader.iter_witness()) {
+        try_read_scalar::<Scalar>(var.value)
+            .map_err(|err| format!("Invalid value of variable {}: {}", var.id, err))?;
+    }
+    for buf in constraints {
+        reader.push_message(buf.clone())?;
+    }
+
+    let proof = prove_circuit(&reader, params, key_options)?;
+    let proof_path = witness.join(DEFAULT_PROOF_PATH);
+    proof.write(File::create(&proof_path)?)?;
+    Ok(proof_path)
+}
+
+/// The ids of the instance variables and the free variable id of the last header.
+fn header_variables(reader: &Reader) -> Option<(Vec<u64>, u64)> {
+    let header = reader.last_header()?;
+    let ids = header.instance_variables()
+        .and_then(|vars| vars.variable_ids())
+        .map_or_else(Vec::new, |ids| ids.safe_slice().to_vec());
+    Some((ids, header.free_variable_id()))
+}
+
+/// Write the results as JSON, with a `proof` path or an `error` for each `witness`.
+pub fn write_manifest(results: &[BatchResult], path: &Path) -> Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{{\n  \"proofs\": [")?;
+    for (i, result) in results.iter().enumerate() {
+        let outcome = match &result.proof {
+            Ok(proof) => format!("\"proof\": {}", json_string(&proof.display().to_string())),
+            Err(err) => format!("\"error\": {}", json_string(err)),
+        };
+        let comma = if i + 1 < results.len() { "," } else { "" };
+        writeln!(file, "    {{ \"witness\": {}, {} }}{}", json_string(&result.witness.display().to_string()), outcome, comma)?;
+    }
+    writeln!(file, "  ]\n}}")?;
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The command `zkif_bellman prove-batch`, given the circuit and the arguments after the command.
+pub fn prove_batch_main(circuit: &Reader, args: &[&str]) -> Result<()> {
+    let mut options = BatchOptions::default();
+    let mut workspace = None;
+    let mut witnesses = vec![];
+
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--threads" => {
+                let threads = args.next().ok_or("Missing value for --threads")?;
+                options.threads = threads.parse().map_err(|_| format!("Invalid number of threads {}", threads))?;
+            }
+            "--workspace" => workspace = Some(PathBuf::from(args.next().ok_or("Missing value for --workspace")?)),
+            "--checked" => options.key_options.checked = true,
+            "--mmap" => options.key_options.mmap = true,
+            "--compress-inputs" => options.key_options.compress_inputs = true,
+            _ if arg.starts_with("--") => return Err(format!("Unexpected argument {}", arg).into()),
+            _ => witnesses.push(PathBuf::from(arg)),
+        }
+    }
+    if witnesses.is_empty() {
+        return Err("Missing witness workspaces.".into());
+    }
+
+    let workspace = match workspace {
+        Some(workspace) => workspace,
+        None => std::env::current_dir()?,
+    };
+    let results = prove_batch(circuit, &workspace, &witnesses, &options)?;
+
+    let manifest = workspace.join(DEFAULT_MANIFEST_PATH);
+    write_manifest(&results, &manifest)?;
+    eprintln!("Written manifest into {}", manifest.display());
+
+    let failed = results.iter().filter(|result| result.proof.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} witnesses could not be proven.", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_prove_batch() -> Result<()> {
+    use std::fs::create_dir_all;
+    use bellman::groth16::{prepare_verifying_key, verify_proof, Proof};
+    use zkinterface::Messages;
+    use crate::gadget_circuits::gadget_main;
+    use crate::zkif_backend::setup;
+
+    let dir = Path::new("local/test_prove_batch");
+    let (a, b, other, missing) = (dir.join("a"), dir.join("b"), dir.join("other"), dir.join("missing"));
+    create_dir_all(dir)?;
+    gadget_main::<Scalar>(&["multipack", "--inputs", "0102", "--out", a.to_str().unwrap()])?;
+    gadget_main::<Scalar>(&["multipack", "--inputs", "0304", "--out", b.to_str().unwrap()])?;
+    gadget_main::<Scalar>(&["multipack", "--inputs", "010203", "--out", other.to_str().unwrap()])?;
+
+    let circuit = read_workspace(std::slice::from_ref(&a))?;
+    setup(&circuit, dir)?;
+
+    // The witness of b with a value that is not a field element.
+    let bad = dir.join("bad");
+    create_dir_all(&bad)?;
+    let messages = Messages::from(&read_workspace(std::slice::from_ref(&b))?);
+    let mut witness = messages.witnesses[0].clone();
+    let values = witness.assigned_variables.values.as_mut().unwrap();
+    values[..32].copy_from_slice(&[255; 32]);
+    let mut file = File::create(bad.join("bad.zkif"))?;
+    messages.circuit_headers[0].write_into(&mut file)?;
+    witness.write_into(&mut file)?;
+
+    let witnesses = [a.clone(), missing, b.clone(), other, bad];
+    let results = prove_batch(&circuit, dir, &witnesses, &BatchOptions { threads: 2, ..BatchOptions::default() })?;
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].proof, Ok(a.join(DEFAULT_PROOF_PATH)));
+    assert!(results[1].proof.is_err());
+    assert_eq!(results[2].proof, Ok(b.join(DEFAULT_PROOF_PATH)));
+    assert!(results[3].proof.as_ref().unwrap_err().contains("do not match"));
+    assert!(results[4].proof.as_ref().unwrap_err().contains("Invalid value"));
+
+    // Each proof is valid for its own instance.
+    let pvk = prepare_verifying_key(&read_parameters::<Bls12>(&dir.join(DEFAULT_KEY_PATH), false)?.vk);
+    let proof_a = Proof::<Bls12>::read(File::open(a.join(DEFAULT_PROOF_PATH))?)?;
+    let proof_b = Proof::<Bls12>::read(File::open(b.join(DEFAULT_PROOF_PATH))?)?;
+    assert!(verify_proof(&pvk, &proof_a, &[Scalar::from(0x0201)]).is_ok());
+    assert!(verify_proof(&pvk, &proof_b, &[Scalar::from(0x0403)]).is_ok());
+    assert!(verify_proof(&pvk, &proof_b, &[Scalar::from(0x0201)]).is_err());
+
+    let manifest = dir.join(DEFAULT_MANIFEST_PATH);
+    write_manifest(&results, &manifest)?;
+    let manifest = std::fs::read_to_string(manifest)?;
+    assert_eq!(manifest.matches("\"proof\"").count(), 2);
+    assert_eq!(manifest.matches("\"error\"").count(), 3);
+
+    // From the command line, with the key in another directory than the current one.
+    std::fs::remove_file(dir.join(DEFAULT_MANIFEST_PATH))?;
+    prove_batch_main(&circuit, &["--workspace", dir.to_str().unwrap(), a.to_str().unwrap()])?;
+    assert!(dir.join(DEFAULT_MANIFEST_PATH).exists());
+    Ok(())
+}
@@ -1,8 +1,16 @@
 use zkinterface::{Reader, Result};
-use zkinterface_bellman::zkif_backend::{setup, prove, verify, validate};
+use zkinterface_bellman::zkif_backend::{setup_with_options, prove_with_options, verify_with_options, rerandomize, validate};
+use zkinterface_bellman::parameters::KeyOptions;
+use zkinterface_bellman::compression::decompress;
+use zkinterface_bellman::gadget_circuits::gadget_main;
+use zkinterface_bellman::json::push_messages;
+use zkinterface_bellman::codegen::codegen_main;
+use zkinterface_bellman::batch::prove_batch_main;
+use zkinterface_bellman::circom::{circom_paths, from_circom_main, read_circom, to_circom_main};
 use std::io;
 use std::io::Read;
 use std::env;
+use std::path::PathBuf;
 use bls12_381::Scalar as Bls12Scalar;
 
 
@@ -24,7 +32,46 @@ Generate a proof using the public parameters:
 
     zkif_bellman prove <workspace>
 
-The circuit and witness are read from stdin in zkInterface format.
+Verify a proof:
+
+    zkif_bellman verify <workspace>
+
+Prove many witnesses of the circuit on stdin, each in a workspace with its circuit header and witness,
+with up to <n> proofs at the same time, and write a summary into bellman-batch.json of the workspace of the key:
+
+    zkif_bellman prove-batch [--threads <n>] [--workspace <workspace>] <witness workspace>…
+
+Replace a proof by a fresh proof of the same instance, which cannot be linked to the original:
+
+    zkif_bellman rerandomize <workspace>
+
+With --checked, prove, prove-batch, verify, and rerandomize check that all points of the parameters are valid, for untrusted keys.
+With --mmap, the parameters are memory-mapped instead of read entirely into memory.
+With --compress-inputs, given to all of setup, prove, prove-batch, verify, and rerandomize, the instance variables are private,
+and the only public input is a SHA-256 commitment to their values.
+
+Export the circuit of a gadget built into bellman (sha256, blake2s, and, xor, uint32-add, multipack, lookup):
+
+    zkif_bellman gadget <name> [--inputs <input>…] [--target prover|preprocessing] --out <workspace>
+
+Convert a circom circuit and optionally its witness into a zkInterface workspace:
+
+    zkif_bellman from-circom <circuit.r1cs> [<witness.wtns>] [--gzip|--zstd] --out <workspace>
+
+Convert a circuit and optionally its witness from stdin into circom files:
+
+    zkif_bellman to-circom <circuit.r1cs> [<witness.wtns>]
+
+Generate the Rust source of a bellman Circuit from the circuit on stdin:
+
+    zkif_bellman codegen [--name <Circuit>] [--crate <path>] [--out <module.rs>]
+
+The circuit and witness are read from stdin in zkInterface format, optionally compressed with gzip or zstd.
+Messages in JSON form are accepted too, as written by flatc, with field elements as decimal or 0x-hex strings.
+With --format circom, they are read from circom files instead, for instance:
+
+    zkif_bellman prove --format circom circuit.r1cs witness.wtns
+
 The filenames of keys and proofs are derived from the workspace argument; defaults to the current directory.
 
 ";
@@ -37,23 +84,124 @@ pub fn main() -> Result<()> {
         return Err("Missing command.".into());
     }
 
-    let mut reader = Reader::new();
-    let mut buffer = vec![];
-    io::stdin().read_to_end(&mut buffer)?;
-    reader.push_message(buffer)?;
+    if args[1] == "gadget" {
+        return gadget_main::<Bls12Scalar>(&args[2..]);
+    }
+    if args[1] == "from-circom" {
+        return from_circom_main::<Bls12Scalar>(&args[2..]);
+    }
+    if args[1] == "to-circom" {
+        return to_circom_main::<Bls12Scalar>(&read_stdin()?, &args[2..]);
+    }
+    if args[1] == "codegen" {
+        return codegen_main::<Bls12Scalar>(&read_stdin()?, &args[2..]);
+    }
+    if args[1] == "prove-batch" {
+        return prove_batch_main(&read_stdin()?, &args[2..]);
+    }
+
+    let parsed = match parse_args(&args[2..]) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}", USAGE);
+            return Err(err);
+        }
+    };
+    let key_options = parsed.key_options;
+    let reader = match &parsed.circom {
+        Some((r1cs, wtns)) => read_circom::<Bls12Scalar>(r1cs, wtns.as_deref())?,
+        None => read_stdin()?,
+    };
 
     let command = args[1];
-    let workspace = env::current_dir()?;
+    let workspace = match parsed.workspace {
+        Some(workspace) => PathBuf::from(workspace),
+        None => env::current_dir()?,
+    };
 
-    match &command[..] {
+    match command {
         "validate" => validate::<Bls12Scalar>(&reader, false),
         "print" => validate::<Bls12Scalar>(&reader, true),
-        "setup" => setup(&reader, &workspace),
-        "prove" => prove(&reader, &workspace),
-        "verify" => verify(&reader, &workspace),
+        "setup" => setup_with_options(&reader, &workspace, &key_options),
+        "prove" => prove_with_options(&reader, &workspace, &key_options),
+        "verify" => verify_with_options(&reader, &workspace, &key_options),
+        "rerandomize" => rerandomize(&reader, &workspace, &key_options),
         _ => {
             eprintln!("{}", USAGE);
             Err(format!("Unknown command {}", command).into())
         }
     }
 }
+
+/// The arguments of the commands that read a circuit, after the command name.
+#[derive(Debug, Default, PartialEq)]
+struct Args<'a> {
+    key_options: KeyOptions,
+    /// The circom circuit and witness to read instead of stdin.
+    circom: Option<(PathBuf, Option<PathBuf>)>,
+    workspace: Option<&'a str>,
+}
+
+fn parse_args<'a>(args: &[&'a str]) -> Result<Args<'a>> {
+    let mut parsed = Args::default();
+    let mut circom = false;
+    let mut positional = vec![];
+
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--checked" => parsed.key_options.checked = true,
+            "--mmap" => parsed.key_options.mmap = true,
+            "--compress-inputs" => parsed.key_options.compress_inputs = true,
+            "--format" => match args.next() {
+                Some("circom") => circom = true,
+                Some("zkif") => circom = false,
+                other => return Err(format!("Unknown format {:?}", other).into()),
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unexpected argument {}", arg).into()),
+            _ => positional.push(arg),
+        }
+    }
+
+    if circom {
+        let (r1cs, wtns, others) = circom_paths(&positional)?;
+        parsed.circom = Some((r1cs, wtns));
+        positional = others;
+    }
+
+    match positional[..] {
+        [] => {}
+        [workspace] => parsed.workspace = Some(workspace),
+        _ => return Err(format!("Unexpected arguments {:?}", positional).into()),
+    }
+    Ok(parsed)
+}
+
+fn read_stdin() -> Result<Reader> {
+    let mut reader = Reader::new();
+    let mut buffer = vec![];
+    decompress(io::stdin())?.read_to_end(&mut buffer)?;
+    push_messages(&mut reader, buffer)?;
+    Ok(reader)
+}
+
+
+#[test]
+fn test_parse_args() -> Result<()> {
+    // prove <workspace>
+    let parsed = parse_args(&["local/ws"])?;
+    assert_eq!(parsed, Args { workspace: Some("local/ws"), ..Args::default() });
+
+    let parsed = parse_args(&["--mmap", "--format", "zkif"])?;
+    assert_eq!(parsed, Args { key_options: KeyOptions { mmap: true, ..KeyOptions::default() }, ..Args::default() });
+
+    let parsed = parse_args(&["--format", "circom", "c.r1cs", "w.wtns", "local/ws", "--checked"])?;
+    assert_eq!(parsed.circom, Some((PathBuf::from("c.r1cs"), Some(PathBuf::from("w.wtns")))));
+    assert_eq!(parsed.workspace, Some("local/ws"));
+    assert!(parsed.key_options.checked);
+
+    assert!(parse_args(&["a", "b"]).is_err());
+    assert!(parse_args(&["--unknown"]).is_err());
+    assert!(parse_args(&["--format", "circom"]).is_err());
+    Ok(())
+}
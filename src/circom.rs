@@ -0,0 +1,704 @@
+//! Import and export circuits and witnesses of circom, in its binary formats `.r1cs` and `.wtns`.
+//!
+//! The wires of circom are used as zkInterface variable ids: wire 0 is the constant one,
+//! then come the public outputs, the public inputs, the private inputs, and the internal wires.
+//! The public outputs and inputs are the instance variables, all other wires are private.
+//!
+//! On export, the instance variables become public inputs and all other variables internal wires,
+//! numbered in the order of the header and of the ids. The label of each wire is its zkInterface id.
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use bellman::Circuit;
+use ff::PrimeField;
+use num_bigint::BigUint;
+use zkinterface::{BilinearConstraint, CircuitHeader, ConstraintSystem, Reader, Result, Sink, Variables, Witness};
+use crate::compression::{Compression, CompressedWorkspaceSink, clean_workspace};
+use crate::export::field_maximum;
+use crate::import::{check_field_maximum, try_read_scalar};
+use crate::zkif_cs::{ZkifCS, Target};
+
+const R1CS_MAGIC: &[u8] = b"r1cs";
+const WTNS_MAGIC: &[u8] = b"wtns";
+
+const R1CS_HEADER: u32 = 1;
+const R1CS_CONSTRAINTS: u32 = 2;
+const R1CS_WIRE_TO_LABEL: u32 = 3;
+const R1CS_CUSTOM_GATES_LIST: u32 = 4;
+const R1CS_CUSTOM_GATES_USES: u32 = 5;
+
+const WTNS_HEADER: u32 = 1;
+const WTNS_VALUES: u32 = 2;
+
+/// The content of a circom `.r1cs` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct R1csFile {
+    pub version: u32,
+    /// The prime of the field, little-endian, on as many bytes as each field element.
+    pub prime: Vec<u8>,
+    pub num_wires: u32,
+    pub num_pub_out: u32,
+    pub num_pub_in: u32,
+    pub num_prv_in: u32,
+    pub num_labels: u64,
+    /// The constraints, with wires as variable ids and coefficients of the size of the prime.
+    pub constraints: Vec<BilinearConstraint>,
+    /// The label of each wire, if the file has a map.
+    pub wire_to_label: Vec<u64>,
+}
+
+/// The content of a circom `.wtns` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WtnsFile {
+    pub version: u32,
+    /// The prime of the field, little-endian, on as many bytes as each field element.
+    pub prime: Vec<u8>,
+    /// The value of each wire, little-endian.
+    pub values: Vec<Vec<u8>>,
+}
+
+impl R1csFile {
+    pub fn read(buf: &[u8]) -> Result<Self> {
+        let (version, sections) = read_sections(buf, R1CS_MAGIC)?;
+
+        if sections.contains_key(&R1CS_CUSTOM_GATES_LIST) || sections.contains_key(&R1CS_CUSTOM_GATES_USES) {
+            return Err("Custom gates of circom are not supported.".into());
+        }
+
+        let mut header = Bytes(section(&sections, R1CS_HEADER, "header")?);
+        let field_size = header.u32()? as usize;
+        let prime = header.take(field_size)?.to_vec();
+        let num_wires = header.u32()?;
+        let num_pub_out = header.u32()?;
+        let num_pub_in = header.u32()?;
+        let num_prv_in = header.u32()?;
+        let num_labels = header.u64()?;
+        let num_constraints = header.u32()?;
+        header.end()?;
+
+        let mut body = Bytes(section(&sections, R1CS_CONSTRAINTS, "constraints")?);
+        let mut constraints = Vec::new();
+        for _ in 0..num_constraints {
+            constraints.push(BilinearConstraint {
+                linear_combination_a: body.linear_combination(field_size, num_wires)?,
+                linear_combination_b: body.linear_combination(field_size, num_wires)?,
+                linear_combination_c: body.linear_combination(field_size, num_wires)?,
+            });
+        }
+        body.end()?;
+
+        let mut wire_to_label = vec![];
+        if let Some(map) = sections.get(&R1CS_WIRE_TO_LABEL) {
+            let mut map = Bytes(map);
+            for _ in 0..num_wires {
+                wire_to_label.push(map.u64()?);
+            }
+            map.end()?;
+        }
+
+        Ok(R1csFile { version, prime, num_wires, num_pub_out, num_pub_in, num_prv_in, num_labels, constraints, wire_to_label })
+    }
+
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read(&read_bytes(path.as_ref())?)
+            .map_err(|err| format!("Cannot read {}: {}", path.as_ref().display(), err).into())
+    }
+
+    /// Write in the binary format, with the sections header, constraints, and the map of labels if any.
+    pub fn write_into(&self, writer: &mut impl Write) -> Result<()> {
+        let field_size = self.prime.len();
+
+        let mut header = (field_size as u32).to_le_bytes().to_vec();
+        header.extend_from_slice(&self.prime);
+        for n in &[self.num_wires, self.num_pub_out, self.num_pub_in, self.num_prv_in] {
+            header.extend_from_slice(&n.to_le_bytes());
+        }
+        header.extend_from_slice(&self.num_labels.to_le_bytes());
+        header.extend_from_slice(&(self.constraints.len() as u32).to_le_bytes());
+
+        let mut body = vec![];
+        for constraint in &self.constraints {
+            for lc in &[&constraint.linear_combination_a, &constraint.linear_combination_b, &constraint.linear_combination_c] {
+                write_linear_combination(&mut body, lc, field_size)?;
+            }
+        }
+
+        let mut sections = vec![(R1CS_HEADER, header), (R1CS_CONSTRAINTS, body)];
+        if !self.wire_to_label.is_empty() {
+            let map = self.wire_to_label.iter().flat_map(|label| label.to_le_bytes().to_vec()).collect();
+            sections.push((R1CS_WIRE_TO_LABEL, map));
+        }
+        write_sections(writer, R1CS_MAGIC, self.version, &sections)
+    }
+
+    /// The number of public wires, outputs then inputs, after the constant one.
+    pub fn num_public(&self) -> u32 {
+        self.num_pub_out + self.num_pub_in
+    }
+}
+
+impl WtnsFile {
+    pub fn read(buf: &[u8]) -> Result<Self> {
+        let (version, sections) = read_sections(buf, WTNS_MAGIC)?;
+
+        let mut header = Bytes(section(&sections, WTNS_HEADER, "header")?);
+        let field_size = header.u32()? as usize;
+        let prime = header.take(field_size)?.to_vec();
+        let num_values = header.u32()?;
+        header.end()?;
+
+        let mut body = Bytes(section(&sections, WTNS_VALUES, "values")?);
+        let values = (0..num_values)
+            .map(|_| body.take(field_size).map(|v| v.to_vec()))
+            .collect::<Result<_>>()?;
+        body.end()?;
+
+        Ok(WtnsFile { version, prime, values })
+    }
+
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read(&read_bytes(path.as_ref())?)
+            .map_err(|err| format!("Cannot read {}: {}", path.as_ref().display(), err).into())
+    }
+
+    /// Write in the binary format.
+    pub fn write_into(&self, writer: &mut impl Write) -> Result<()> {
+        let field_size = self.prime.len();
+
+        let mut header = (field_size as u32).to_le_bytes().to_vec();
+        header.extend_from_slice(&self.prime);
+        header.extend_from_slice(&(self.values.len() as u32).to_le_bytes());
+
+        let mut body = vec![];
+        for value in &self.values {
+            if value.len() != field_size {
+                return Err(format!("A value has {} bytes instead of {}.", value.len(), field_size).into());
+            }
+            body.extend_from_slice(value);
+        }
+        write_sections(writer, WTNS_MAGIC, self.version, &[(WTNS_HEADER, header), (WTNS_VALUES, body)])
+    }
+}
+
+/// Check that a circom prime is the modulus of the field of Scalar.
+pub fn check_prime<Scalar: PrimeField>(prime: &[u8]) -> Result<()> {
+    let supported = BigUint::from_bytes_le(&field_maximum::<Scalar>()) + 1u32;
+    let prime = BigUint::from_bytes_le(prime);
+    if prime != supported {
+        return Err(format!(
+            "The circuit is over the prime {}, but the proving system supports {}. Compile it with `circom --prime bls12381`.",
+            prime, supported).into());
+    }
+    Ok(())
+}
+
+/// Write a circom circuit, and optionally its witness, as zkInterface messages into a sink.
+pub fn write_circom_messages(r1cs: &R1csFile, wtns: Option<&WtnsFile>, sink: &mut impl Sink) -> Result<()> {
+    let num_public = r1cs.num_public() as u64;
+    let num_wires = r1cs.num_wires as u64;
+
+    if let Some(wtns) = wtns {
+        if wtns.prime != r1cs.prime {
+            return Err("The witness and the circuit are over different fields.".into());
+        }
+        if wtns.values.len() as u64 != num_wires {
+            return Err(format!("The witness has {} values, but the circuit has {} wires.", wtns.values.len(), num_wires).into());
+        }
+    }
+    let values = |ids: &[u64]| wtns.map(|wtns|
+        ids.iter().flat_map(|id| wtns.values[*id as usize].iter().cloned()).collect::<Vec<u8>>());
+
+    let instance_ids: Vec<u64> = (1..=num_public).collect();
+    let field_maximum = BigUint::from_bytes_le(&r1cs.prime) - 1u32;
+
+    sink.push_header(CircuitHeader {
+        instance_variables: Variables { values: values(&instance_ids), variable_ids: instance_ids },
+        free_variable_id: num_wires,
+        field_maximum: Some(field_maximum.to_bytes_le()),
+        configuration: None,
+    })?;
+
+    sink.push_constraints(ConstraintSystem { constraints: r1cs.constraints.clone() })?;
+
+    if wtns.is_some() {
+        let private_ids: Vec<u64> = (num_public + 1..num_wires).collect();
+        sink.push_witness(Witness {
+            assigned_variables: Variables { values: values(&private_ids), variable_ids: private_ids },
+        })?;
+    }
+    Ok(())
+}
+
+/// Read a circom circuit, and optionally its witness, into messages for ZKIFCircuit.
+/// The prime must be the modulus of the field of Scalar.
+pub fn read_circom<Scalar: PrimeField>(r1cs_path: impl AsRef<Path>, wtns_path: Option<&Path>) -> Result<Reader> {
+    let r1cs = R1csFile::read_file(r1cs_path)?;
+    check_prime::<Scalar>(&r1cs.prime)?;
+    let wtns = wtns_path.map(WtnsFile::read_file).transpose()?;
+
+    let mut sink = ReaderSink(Reader::new());
+    write_circom_messages(&r1cs, wtns.as_ref(), &mut sink)?;
+    Ok(sink.0)
+}
+
+/// Convert a circom circuit, and optionally its witness, into a zkInterface workspace.
+pub fn circom_to_workspace<Scalar: PrimeField>(
+    r1cs_path: impl AsRef<Path>,
+    wtns_path: Option<&Path>,
+    workspace: impl AsRef<Path>,
+    compression: Compression,
+) -> Result<Vec<PathBuf>> {
+    let r1cs = R1csFile::read_file(r1cs_path)?;
+    check_prime::<Scalar>(&r1cs.prime)?;
+    let wtns = wtns_path.map(WtnsFile::read_file).transpose()?;
+
+    let workspace = workspace.as_ref();
+    if workspace.exists() {
+        clean_workspace(workspace)?;
+    }
+    let mut sink = CompressedWorkspaceSink::new(workspace, compression)?;
+    write_circom_messages(&r1cs, wtns.as_ref(), &mut sink)?;
+
+    let mut files = vec![sink.file_path("header"), sink.file_path("constraints_0")];
+    if wtns.is_some() {
+        files.push(sink.file_path("witness"));
+    }
+    sink.finish()?;
+    Ok(files)
+}
+
+/// Convert zkInterface messages into a circom circuit, and a witness if the messages have all values.
+/// Partial values are ignored.
+pub fn zkif_to_circom<Scalar: PrimeField>(reader: &Reader) -> Result<(R1csFile, Option<WtnsFile>)> {
+    let header = reader.last_header().ok_or("No CircuitHeader in the messages.")?;
+    if let Some(field_maximum) = header.field_maximum() {
+        check_field_maximum::<Scalar>(field_maximum)?;
+    }
+    let instance_vars = reader.instance_variables().ok_or("No instance variables in the header.")?;
+    let private_vars = reader.private_variables().ok_or("Invalid free_variable_id in the header.")?;
+
+    // The wire of each variable id.
+    let mut wires = HashMap::<u64, u32>::new();
+    let mut wire_to_label = vec![0];
+    wires.insert(0, 0);
+    for var in instance_vars.iter().chain(&private_vars) {
+        if wires.insert(var.id, wire_to_label.len() as u32).is_some() {
+            return Err(format!("The variable {} is declared twice.", var.id).into());
+        }
+        wire_to_label.push(var.id);
+    }
+
+    let encode = |value: &[u8]| -> Result<Vec<u8>> {
+        Ok(try_read_scalar::<Scalar>(value)?.to_repr().as_ref().to_vec())
+    };
+    let lc = |terms: &[zkinterface::consumers::reader::Variable]| -> Result<Variables> {
+        let mut variable_ids = vec![];
+        let mut values = vec![];
+        for term in terms {
+            let wire = wires.get(&term.id).ok_or_else(|| format!("The variable {} is not declared.", term.id))?;
+            variable_ids.push(*wire as u64);
+            values.extend(encode(term.value)?);
+        }
+        Ok(Variables { variable_ids, values: Some(values) })
+    };
+    let constraints = reader.iter_constraints().map(|constraint| Ok(BilinearConstraint {
+        linear_combination_a: lc(&constraint.a)?,
+        linear_combination_b: lc(&constraint.b)?,
+        linear_combination_c: lc(&constraint.c)?,
+    })).collect::<Result<Vec<_>>>()?;
+
+    let prime = encode_prime::<Scalar>();
+    let num_values = instance_vars.iter().chain(&private_vars).filter(|var| var.has_value()).count();
+    let wtns = if num_values < wire_to_label.len() - 1 {
+        None
+    } else {
+        let mut values = vec![encode(&[1])?];
+        for var in instance_vars.iter().chain(&private_vars) {
+            values.push(encode(var.value)?);
+        }
+        Some(WtnsFile { version: 2, prime: prime.clone(), values })
+    };
+
+    let r1cs = R1csFile {
+        version: 1,
+        prime,
+        num_wires: wire_to_label.len() as u32,
+        num_pub_out: 0,
+        num_pub_in: instance_vars.len() as u32,
+        num_prv_in: 0,
+        num_labels: header.free_variable_id(),
+        constraints,
+        wire_to_label,
+    };
+    Ok((r1cs, wtns))
+}
+
+/// Synthesize a bellman circuit through ZkifCS into a circom circuit, and a witness with the Prover target.
+pub fn circuit_to_circom<Scalar: PrimeField, C: Circuit<Scalar>>(circuit: C, target: Target) -> Result<(R1csFile, Option<WtnsFile>)> {
+    let mut cs = ZkifCS::<Scalar, _>::from_sink(ReaderSink(Reader::new()), target);
+    circuit.synthesize(&mut cs)?;
+    let sink = cs.finish_into_sink("circom")?;
+    zkif_to_circom::<Scalar>(&sink.0)
+}
+
+/// Write zkInterface messages as circom files. The witness is written if a path is given.
+pub fn write_circom_files<Scalar: PrimeField>(reader: &Reader, r1cs_path: &Path, wtns_path: Option<&Path>) -> Result<()> {
+    let (r1cs, wtns) = zkif_to_circom::<Scalar>(reader)?;
+    r1cs.write_into(&mut File::create(r1cs_path)?)?;
+    eprintln!("Written {}", r1cs_path.display());
+
+    if let Some(wtns_path) = wtns_path {
+        let wtns = wtns.ok_or("The messages do not have the values of all variables.")?;
+        wtns.write_into(&mut File::create(wtns_path)?)?;
+        eprintln!("Written {}", wtns_path.display());
+    }
+    Ok(())
+}
+
+/// The command `zkif_bellman to-circom`, given the messages and the arguments after the command.
+pub fn to_circom_main<Scalar: PrimeField>(reader: &Reader, args: &[&str]) -> Result<()> {
+    let (r1cs, wtns, others) = circom_paths(args)?;
+    if !others.is_empty() {
+        return Err(format!("Unexpected arguments {:?}", others).into());
+    }
+    write_circom_files::<Scalar>(reader, &r1cs, wtns.as_deref())
+}
+
+/// The prime of the field of Scalar, on as many bytes as an element.
+fn encode_prime<Scalar: PrimeField>() -> Vec<u8> {
+    let mut prime = (BigUint::from_bytes_le(&field_maximum::<Scalar>()) + 1u32).to_bytes_le();
+    prime.resize(Scalar::Repr::default().as_ref().len(), 0);
+    prime
+}
+
+/// Keep messages in a Reader.
+struct ReaderSink(Reader);
+
+impl Sink for ReaderSink {
+    fn push_header(&mut self, header: CircuitHeader) -> Result<()> {
+        let mut buf = vec![];
+        header.write_into(&mut buf)?;
+        self.0.push_message(buf)
+    }
+
+    fn push_constraints(&mut self, cs: ConstraintSystem) -> Result<()> {
+        let mut buf = vec![];
+        cs.write_into(&mut buf)?;
+        self.0.push_message(buf)
+    }
+
+    fn push_witness(&mut self, witness: Witness) -> Result<()> {
+        let mut buf = vec![];
+        witness.write_into(&mut buf)?;
+        self.0.push_message(buf)
+    }
+}
+
+/// Split the arguments of a command into the circom files, `.r1cs` and optionally `.wtns`, and the other arguments.
+pub fn circom_paths<'a>(args: &[&'a str]) -> Result<(PathBuf, Option<PathBuf>, Vec<&'a str>)> {
+    let mut r1cs = None;
+    let mut wtns = None;
+    let mut others = vec![];
+    for arg in args {
+        if arg.ends_with(".r1cs") {
+            r1cs = Some(PathBuf::from(arg));
+        } else if arg.ends_with(".wtns") {
+            wtns = Some(PathBuf::from(arg));
+        } else {
+            others.push(*arg);
+        }
+    }
+    let r1cs = r1cs.ok_or("Missing the circom circuit (.r1cs file).")?;
+    Ok((r1cs, wtns, others))
+}
+
+const FROM_CIRCOM_USAGE: &str = "Convert a circom circuit and optionally its witness into a zkInterface workspace.
+
+    zkif_bellman from-circom <circuit.r1cs> [<witness.wtns>] [--gzip|--zstd] --out <workspace>
+";
+
+/// The command `zkif_bellman from-circom`, given the arguments after the command.
+pub fn from_circom_main<Scalar: PrimeField>(args: &[&str]) -> Result<()> {
+    let (r1cs, wtns, others) = circom_paths(args)?;
+    let mut compression = Compression::None;
+    let mut workspace = None;
+
+    let mut others = others.into_iter();
+    while let Some(arg) = others.next() {
+        match arg {
+            "--out" => workspace = Some(PathBuf::from(others.next().ok_or("Missing value for --out")?)),
+            "--gzip" => compression = Compression::Gzip,
+            "--zstd" => compression = Compression::Zstd,
+            _ => {
+                eprintln!("{}", FROM_CIRCOM_USAGE);
+                return Err(format!("Unexpected argument {}", arg).into());
+            }
+        }
+    }
+    let workspace = workspace.map_or_else(env::current_dir, Ok)?;
+
+    for file in circom_to_workspace::<Scalar>(&r1cs, wtns.as_deref(), &workspace, compression)? {
+        eprintln!("Written {}", file.display());
+    }
+    Ok(())
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read the magic, the version, and the sections by type.
+fn read_sections<'a>(buf: &'a [u8], magic: &[u8]) -> Result<(u32, BTreeMap<u32, &'a [u8]>)> {
+    let mut bytes = Bytes(buf);
+    if bytes.take(magic.len())? != magic {
+        return Err(format!("Not a circom file, expected the magic {:?}.", String::from_utf8_lossy(magic)).into());
+    }
+    let version = bytes.u32()?;
+    if version == 0 || version > 2 {
+        return Err(format!("Unsupported version {} of the circom format.", version).into());
+    }
+
+    let num_sections = bytes.u32()?;
+    let mut sections = BTreeMap::new();
+    for _ in 0..num_sections {
+        let section_type = bytes.u32()?;
+        let size = bytes.u64()? as usize;
+        if sections.insert(section_type, bytes.take(size)?).is_some() {
+            return Err(format!("Duplicate section {}.", section_type).into());
+        }
+    }
+    bytes.end()?;
+    Ok((version, sections))
+}
+
+fn write_sections(writer: &mut impl Write, magic: &[u8], version: u32, sections: &[(u32, Vec<u8>)]) -> Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&(sections.len() as u32).to_le_bytes())?;
+    for (section_type, data) in sections {
+        writer.write_all(&section_type.to_le_bytes())?;
+        writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+fn write_linear_combination(buf: &mut Vec<u8>, lc: &Variables, field_size: usize) -> Result<()> {
+    let values = lc.values.as_deref().unwrap_or(&[]);
+    if values.len() != lc.variable_ids.len() * field_size {
+        return Err(format!("The coefficients must have {} bytes each.", field_size).into());
+    }
+    buf.extend_from_slice(&(lc.variable_ids.len() as u32).to_le_bytes());
+    for (id, coeff) in lc.variable_ids.iter().zip(values.chunks(field_size)) {
+        buf.extend_from_slice(&(*id as u32).to_le_bytes());
+        buf.extend_from_slice(coeff);
+    }
+    Ok(())
+}
+
+fn section<'a>(sections: &BTreeMap<u32, &'a [u8]>, section_type: u32, name: &str) -> Result<&'a [u8]> {
+    sections.get(&section_type).cloned()
+        .ok_or_else(|| format!("Missing the {} section.", name).into())
+}
+
+/// A cursor over little-endian data.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err("Unexpected end of data.".into());
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut le = [0; 4];
+        le.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(le))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut le = [0; 8];
+        le.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(le))
+    }
+
+    fn end(&self) -> Result<()> {
+        if !self.0.is_empty() {
+            return Err(format!("{} unexpected bytes at the end of a section.", self.0.len()).into());
+        }
+        Ok(())
+    }
+
+    fn linear_combination(&mut self, field_size: usize, num_wires: u32) -> Result<Variables> {
+        let num_terms = self.u32()?;
+        let mut variable_ids = vec![];
+        let mut values = vec![];
+        for _ in 0..num_terms {
+            let wire = self.u32()?;
+            if wire >= num_wires {
+                return Err(format!("The wire {} does not exist.", wire).into());
+            }
+            variable_ids.push(wire as u64);
+            values.extend_from_slice(self.take(field_size)?);
+        }
+        Ok(Variables { variable_ids, values: Some(values) })
+    }
+}
+
+
+#[test]
+fn test_import_circom() -> Result<()> {
+    use std::fs::{create_dir_all, write};
+    use bls12_381::Scalar;
+    use crate::compression::read_workspace;
+    use crate::zkif_backend::validate;
+
+    // out = a * b, with the output public, as written by circom.
+    fn section(buf: &mut Vec<u8>, section_type: u32, data: &[u8]) {
+        buf.extend_from_slice(&section_type.to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        buf.extend_from_slice(data);
+    }
+    fn element(n: u64) -> Vec<u8> {
+        let mut bytes = n.to_le_bytes().to_vec();
+        bytes.resize(32, 0);
+        bytes
+    }
+    let mut prime = field_maximum::<Scalar>();
+    prime[0] += 1;
+
+    let mut header = 32u32.to_le_bytes().to_vec();
+    header.extend_from_slice(&prime);
+    for n in &[4u32, 1, 0, 2] { header.extend_from_slice(&n.to_le_bytes()); }
+    header.extend_from_slice(&4u64.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+
+    let mut constraints = vec![];
+    for wire in &[2u32, 3, 1] {
+        constraints.extend_from_slice(&1u32.to_le_bytes());
+        constraints.extend_from_slice(&wire.to_le_bytes());
+        constraints.extend_from_slice(&element(1));
+    }
+
+    let mut r1cs = b"r1cs".to_vec();
+    r1cs.extend_from_slice(&1u32.to_le_bytes());
+    r1cs.extend_from_slice(&3u32.to_le_bytes());
+    section(&mut r1cs, R1CS_HEADER, &header);
+    section(&mut r1cs, R1CS_CONSTRAINTS, &constraints);
+    section(&mut r1cs, R1CS_WIRE_TO_LABEL, &[0, 1, 2, 3].iter().flat_map(|l: &u64| l.to_le_bytes().to_vec()).collect::<Vec<u8>>());
+
+    let wtns = |values: &[u64]| {
+        let mut header = 32u32.to_le_bytes().to_vec();
+        header.extend_from_slice(&prime);
+        header.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        let mut wtns = b"wtns".to_vec();
+        wtns.extend_from_slice(&2u32.to_le_bytes());
+        wtns.extend_from_slice(&2u32.to_le_bytes());
+        section(&mut wtns, WTNS_HEADER, &header);
+        section(&mut wtns, WTNS_VALUES, &values.iter().flat_map(|v| element(*v)).collect::<Vec<u8>>());
+        wtns
+    };
+
+    let dir = PathBuf::from("local/test_import_circom");
+    create_dir_all(&dir)?;
+    let (r1cs_path, wtns_path, bad_path) = (dir.join("mul.r1cs"), dir.join("mul.wtns"), dir.join("bad.wtns"));
+    write(&r1cs_path, &r1cs)?;
+    write(&wtns_path, wtns(&[1, 12, 3, 4]))?;
+    write(&bad_path, wtns(&[1, 13, 3, 4]))?;
+
+    let parsed = R1csFile::read_file(&r1cs_path)?;
+    assert_eq!((parsed.num_wires, parsed.num_public(), parsed.constraints.len()), (4, 1, 1));
+    assert_eq!(parsed.wire_to_label, vec![0, 1, 2, 3]);
+    let mut written = vec![];
+    parsed.write_into(&mut written)?;
+    assert_eq!(written, r1cs);
+
+    let reader = read_circom::<Scalar>(&r1cs_path, Some(&wtns_path))?;
+    validate::<Scalar>(&reader, false)?;
+    assert_eq!(reader.instance_variables().unwrap()[0].value, &element(12)[..]);
+    assert!(validate::<Scalar>(&read_circom::<Scalar>(&r1cs_path, Some(&bad_path))?, false).is_err());
+    read_circom::<Scalar>(&r1cs_path, None)?;
+
+    // Convert to a workspace.
+    from_circom_main::<Scalar>(&[r1cs_path.to_str().unwrap(), wtns_path.to_str().unwrap(), "--zstd", "--out", "local/test_import_circom/workspace"])?;
+    validate::<Scalar>(&read_workspace(&[dir.join("workspace")])?, false)?;
+
+    // Another field.
+    let mut other_field = r1cs.clone();
+    other_field[4 + 4 + 4 + 12 + 4 + 4] ^= 1;
+    match R1csFile::read(&other_field).and_then(|r1cs| check_prime::<Scalar>(&r1cs.prime)) {
+        Err(err) => assert!(err.to_string().contains("--prime bls12381")),
+        Ok(_) => panic!("Should refuse another prime"),
+    }
+    assert!(R1csFile::read(&r1cs[..r1cs.len() - 1]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_export_circom() -> Result<()> {
+    use std::fs::create_dir_all;
+    use bls12_381::Scalar;
+    use crate::gadget_circuits::BuiltinGadget;
+    use crate::zkif_backend::validate;
+
+    let to_bytes = |r1cs: &R1csFile, wtns: &WtnsFile| -> Result<(Vec<u8>, Vec<u8>)> {
+        let (mut r1cs_bytes, mut wtns_bytes) = (vec![], vec![]);
+        r1cs.write_into(&mut r1cs_bytes)?;
+        wtns.write_into(&mut wtns_bytes)?;
+        Ok((r1cs_bytes, wtns_bytes))
+    };
+
+    let gadget = BuiltinGadget::parse("uint32-add", &[])?;
+    let (r1cs, wtns) = circuit_to_circom::<Scalar, _>(gadget.clone(), Target::Prover)?;
+    let wtns = wtns.unwrap();
+    assert_eq!(r1cs.num_pub_in, 1);
+    assert_eq!(wtns.values.len() as u32, r1cs.num_wires);
+
+    // Byte-exact round-trip through the importer.
+    let (r1cs_bytes, wtns_bytes) = to_bytes(&r1cs, &wtns)?;
+    assert_eq!(R1csFile::read(&r1cs_bytes)?, r1cs);
+    assert_eq!(WtnsFile::read(&wtns_bytes)?, wtns);
+    assert_eq!(to_bytes(&R1csFile::read(&r1cs_bytes)?, &WtnsFile::read(&wtns_bytes)?)?, (r1cs_bytes, wtns_bytes));
+
+    // Through files and zkInterface messages, the same circuit with wires as labels.
+    let dir = PathBuf::from("local/test_export_circom");
+    create_dir_all(&dir)?;
+    let (r1cs_path, wtns_path) = (dir.join("add.r1cs"), dir.join("add.wtns"));
+    let mut cs = ZkifCS::<Scalar, _>::from_sink(ReaderSink(Reader::new()), Target::Prover);
+    gadget.synthesize(&mut cs)?;
+    let reader = cs.finish_into_sink("add")?.0;
+    to_circom_main::<Scalar>(&reader, &[r1cs_path.to_str().unwrap(), wtns_path.to_str().unwrap()])?;
+
+    let imported = read_circom::<Scalar>(&r1cs_path, Some(&wtns_path))?;
+    validate::<Scalar>(&imported, false)?;
+    let (again, again_wtns) = zkif_to_circom::<Scalar>(&imported)?;
+    assert_eq!(again.constraints, r1cs.constraints);
+    assert_eq!(again_wtns.unwrap(), wtns);
+    assert_eq!(again.wire_to_label, (0..r1cs.num_wires as u64).collect::<Vec<_>>());
+
+    // Without a witness.
+    let (_, no_wtns) = circuit_to_circom::<Scalar, _>(BuiltinGadget::parse("xor", &[])?, Target::Preprocessing)?;
+    assert!(no_wtns.is_none());
+
+    // With the values of the instance variables only.
+    let mut partial = Reader::new();
+    for (msg, buf) in reader.into_iter().zip(&reader.messages) {
+        if msg.message_as_witness().is_none() {
+            partial.push_message(buf.clone())?;
+        }
+    }
+    let (partial_r1cs, no_wtns) = zkif_to_circom::<Scalar>(&partial)?;
+    assert_eq!(partial_r1cs.constraints.len(), partial.iter_constraints().count());
+    assert!(no_wtns.is_none());
+    assert!(write_circom_files::<Scalar>(&partial, &dir.join("partial.r1cs"), Some(&dir.join("partial.wtns"))).is_err());
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Generate the Rust source of a bellman Circuit from a zkInterface constraint system.
+//!
+//! The generated circuit embeds the variables and constraints as constant tables, and takes
+//! the values of the variables in a witness struct. It synthesizes exactly like ZKIFCircuit
+//! on the same messages, without parsing them at each run.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use ff::PrimeField;
+use zkinterface::{Reader, Result};
+use zkinterface::consumers::reader::Variable as ZkifVariable;
+use crate::import::{check_field_maximum, try_read_scalar};
+
+/// A circuit generated from the ZoKrates demo messages in src/zokrates/messages: a² + b² = out.
+pub mod zokrates_demo;
+
+/// Options of generate_circuit.
+#[derive(Clone, Debug)]
+pub struct CodegenOptions {
+    /// The name of the circuit struct. The witness struct is named after it, with a `Witness` suffix.
+    pub name: String,
+    /// The path of this crate from the generated module, `zkinterface_bellman`, or `crate` inside of it.
+    pub crate_path: String,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        CodegenOptions {
+            name: "GeneratedCircuit".to_string(),
+            crate_path: "zkinterface_bellman".to_string(),
+        }
+    }
+}
+
+/// Generate a Rust module with a bellman Circuit equivalent to ZKIFCircuit on these messages.
+pub fn generate_circuit<Scalar: PrimeField>(reader: &Reader, options: &CodegenOptions) -> Result<String> {
+    let header = reader.last_header().ok_or("No CircuitHeader in the messages.")?;
+    if let Some(field_maximum) = header.field_maximum() {
+        check_field_maximum::<Scalar>(field_maximum)?;
+    }
+    let instance_vars = reader.instance_variables().ok_or("No instance variables in the header.")?;
+    let private_vars = reader.private_variables().ok_or("Invalid free_variable_id in the header.")?;
+
+    // The index of each variable in the generated table, after the constant one.
+    let mut indexes = HashMap::<u64, usize>::new();
+    indexes.insert(0, 0);
+    for (i, var) in instance_vars.iter().chain(&private_vars).enumerate() {
+        indexes.insert(var.id, i + 1);
+    }
+
+    // Each distinct coefficient once, in order of appearance.
+    let mut constants = BTreeMap::<Vec<u8>, usize>::new();
+    let mut constant_list = vec![];
+    let mut terms = |lc: &[ZkifVariable]| -> Result<String> {
+        let mut out = String::new();
+        for term in lc {
+            let index = indexes.get(&term.id).ok_or_else(|| format!("The variable {} is not declared.", term.id))?;
+            let canonical = try_read_scalar::<Scalar>(term.value)?.to_repr().as_ref().to_vec();
+            let next = constant_list.len();
+            let constant = *constants.entry(canonical.clone()).or_insert_with(|| {
+                constant_list.push(canonical);
+                next
+            });
+            write!(out, "({}, {}), ", index, constant)?;
+        }
+        Ok(out.trim_end_matches(", ").to_string())
+    };
+
+    let mut constraints = String::new();
+    let mut num_constraints = 0;
+    for constraint in reader.iter_constraints() {
+        writeln!(constraints, "    [&[{}], &[{}], &[{}]],", terms(&constraint.a)?, terms(&constraint.b)?, terms(&constraint.c)?)?;
+        num_constraints += 1;
+    }
+
+    let ids = |vars: &[ZkifVariable]| vars.iter().map(|v| v.id.to_string()).collect::<Vec<_>>().join(", ");
+    let constant_bytes = constant_list.iter().map(|le| {
+        let len = le.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        format!("    &{:?},\n", &le[..len])
+    }).collect::<String>();
+
+    Ok(TEMPLATE
+        .replace("{crate}", &options.crate_path)
+        .replace("{name}", &options.name)
+        .replace("{num_instance}", &instance_vars.len().to_string())
+        .replace("{num_private}", &private_vars.len().to_string())
+        .replace("{num_constraints}", &num_constraints.to_string())
+        .replace("{num_constants}", &constant_list.len().to_string())
+        .replace("{instance_ids}", &ids(&instance_vars))
+        .replace("{private_ids}", &ids(&private_vars))
+        .replace("{constants}\n", &constant_bytes)
+        .replace("{constraints}\n", &constraints))
+}
+
+/// The values of the instance and private variables in the messages, in the order of the generated circuit.
+pub fn witness_values<Scalar: PrimeField>(reader: &Reader) -> Result<(Vec<Scalar>, Vec<Scalar>)> {
+    let values = |vars: Vec<ZkifVariable>| -> Result<Vec<Scalar>> {
+        vars.iter().map(|var| {
+            if !var.has_value() {
+                return Err(format!("The variable {} has no value.", var.id).into());
+            }
+            try_read_scalar(var.value)
+        }).collect()
+    };
+    Ok((
+        values(reader.instance_variables().ok_or("No instance variables in the header.")?)?,
+        values(reader.private_variables().ok_or("Invalid free_variable_id in the header.")?)?,
+    ))
+}
+
+const CODEGEN_USAGE: &str = "Generate the Rust source of a bellman Circuit from the messages on stdin.
+
+    zkif_bellman codegen [--name <Circuit>] [--crate <path>] [--out <module.rs>]
+";
+
+/// The command `zkif_bellman codegen`, given the messages and the arguments after the command.
+pub fn codegen_main<Scalar: PrimeField>(reader: &Reader, args: &[&str]) -> Result<()> {
+    let mut options = CodegenOptions::default();
+    let mut out = None;
+
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {}", arg));
+        match arg {
+            "--name" => options.name = value()?.to_string(),
+            "--crate" => options.crate_path = value()?.to_string(),
+            "--out" => out = Some(PathBuf::from(value()?)),
+            _ => {
+                eprintln!("{}", CODEGEN_USAGE);
+                return Err(format!("Unexpected argument {}", arg).into());
+            }
+        }
+    }
+
+    let code = generate_circuit::<Scalar>(reader, &options)?;
+    match out {
+        Some(path) => {
+            File::create(&path)?.write_all(code.as_bytes())?;
+            eprintln!("Written {}", path.display());
+        }
+        None => print!("{}", code),
+    }
+    Ok(())
+}
+
+const TEMPLATE: &str = r#"//! Generated by zkinterface_bellman::codegen from zkInterface messages. Do not edit.
+//!
+//! {num_instance} instance variables, {num_private} private variables, {num_constraints} constraints.
+
+use {crate}::bellman::{Circuit, ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use {crate}::bellman::gadgets::num::AllocatedNum;
+use {crate}::ff::PrimeField;
+use {crate}::import::read_scalar;
+
+/// The zkInterface ids of the instance variables, in order.
+pub const INSTANCE_IDS: [u64; {num_instance}] = [{instance_ids}];
+
+/// The zkInterface ids of the private variables, in order.
+pub const PRIVATE_IDS: [u64; {num_private}] = [{private_ids}];
+
+/// The coefficients, little-endian.
+const CONSTANTS: [&[u8]; {num_constants}] = [
+{constants}
+];
+
+/// The terms (variable, coefficient) of A, B, C in each constraint.
+/// Variable 0 is the constant one, then come the instance and private variables.
+const CONSTRAINTS: [[&[(usize, usize)]; 3]; {num_constraints}] = [
+{constraints}
+];
+
+/// The values of the variables, in the order of INSTANCE_IDS and PRIVATE_IDS.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct {name}Witness<Scalar> {
+    pub instance: Vec<Scalar>,
+    pub private: Vec<Scalar>,
+}
+
+/// The circuit, with a witness to prove, or without for the setup.
+#[derive(Clone, Debug, Default)]
+pub struct {name}<Scalar> {
+    pub witness: Option<{name}Witness<Scalar>>,
+}
+
+impl<Scalar: PrimeField> Circuit<Scalar> for {name}<Scalar> {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        if let Some(witness) = &self.witness {
+            if witness.instance.len() != INSTANCE_IDS.len() || witness.private.len() != PRIVATE_IDS.len() {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+        let value = |values: fn(&{name}Witness<Scalar>) -> &[Scalar], i: usize| {
+            self.witness.as_ref().map(|w| values(w)[i]).ok_or(SynthesisError::AssignmentMissing)
+        };
+
+        let mut vars: Vec<Variable> = vec![CS::one()];
+
+        for (i, id) in INSTANCE_IDS.iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("public_{}", id));
+            let num = AllocatedNum::alloc(&mut cs, || value(|w| &w.instance, i))?;
+            num.inputize(&mut cs)?;
+            vars.push(num.get_variable());
+        }
+
+        for (i, id) in PRIVATE_IDS.iter().enumerate() {
+            let num = AllocatedNum::alloc(cs.namespace(|| format!("private_{}", id)), || value(|w| &w.private, i))?;
+            vars.push(num.get_variable());
+        }
+
+        let constants: Vec<Scalar> = CONSTANTS.iter().map(|le| read_scalar(le)).collect();
+        let lc = |terms: &[(usize, usize)]| {
+            terms.iter().fold(LinearCombination::zero(), |lc, (var, constant)| lc + (constants[*constant], vars[*var]))
+        };
+
+        for (i, [a, b, c]) in CONSTRAINTS.iter().enumerate() {
+            cs.namespace(|| format!("constraint_{}", i)).enforce(|| "", |_| lc(a), |_| lc(b), |_| lc(c));
+        }
+        Ok(())
+    }
+}
+"#;
+
+
+#[test]
+fn test_codegen() -> Result<()> {
+    use std::path::Path;
+    use bellman::Circuit;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::zkif_backend::ZKIFCircuit;
+    use crate::zokrates::read_legacy_file;
+    use self::zokrates_demo::{ZokratesDemo, ZokratesDemoWitness};
+
+    let dir = Path::new("src/zokrates/messages");
+    let mut reader = Reader::new();
+    for name in &["r1cs.zkif", "circuit_r1cs.zkif", "witness.zkif", "circuit_witness.zkif"] {
+        read_legacy_file(&mut reader, dir.join(name))?;
+    }
+
+    // The checked-in module is up to date.
+    let options = CodegenOptions { name: "ZokratesDemo".to_string(), crate_path: "crate".to_string() };
+    let code = generate_circuit::<Scalar>(&reader, &options)?;
+    assert_eq!(code, include_str!("zokrates_demo.rs"), "Regenerate src/codegen/zokrates_demo.rs");
+
+    // The same constraint system as ZKIFCircuit.
+    let (instance, private) = witness_values::<Scalar>(&reader)?;
+    assert_eq!(instance, vec![Scalar::from(5), Scalar::from(0), Scalar::from(25)]);
+
+    let mut expected = TestConstraintSystem::<Scalar>::new();
+    ZKIFCircuit { reader: &reader }.synthesize(&mut expected)?;
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    ZokratesDemo { witness: Some(ZokratesDemoWitness { instance, private }) }.synthesize(&mut cs)?;
+
+    assert!(cs.is_satisfied());
+    assert_eq!(cs.num_inputs(), expected.num_inputs());
+    assert_eq!(cs.num_constraints(), expected.num_constraints());
+    assert_eq!(cs.hash(), expected.hash());
+    assert_eq!(cs.pretty_print(), expected.pretty_print());
+
+    // Wrong values.
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let witness = ZokratesDemoWitness { instance: vec![Scalar::from(5), Scalar::from(0), Scalar::from(26)], private: vec![Scalar::from(25), Scalar::from(0)] };
+    ZokratesDemo { witness: Some(witness) }.synthesize(&mut cs)?;
+    assert!(!cs.is_satisfied());
+    Ok(())
+}
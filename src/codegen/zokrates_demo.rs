@@ -0,0 +1,77 @@
+//! Generated by zkinterface_bellman::codegen from zkInterface messages. Do not edit.
+//!
+//! 3 instance variables, 2 private variables, 3 constraints.
+
+use crate::bellman::{Circuit, ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use crate::bellman::gadgets::num::AllocatedNum;
+use crate::ff::PrimeField;
+use crate::import::read_scalar;
+
+/// The zkInterface ids of the instance variables, in order.
+pub const INSTANCE_IDS: [u64; 3] = [1, 2, 3];
+
+/// The zkInterface ids of the private variables, in order.
+pub const PRIVATE_IDS: [u64; 2] = [4, 5];
+
+/// The coefficients, little-endian.
+const CONSTANTS: [&[u8]; 1] = [
+    &[1],
+];
+
+/// The terms (variable, coefficient) of A, B, C in each constraint.
+/// Variable 0 is the constant one, then come the instance and private variables.
+const CONSTRAINTS: [[&[(usize, usize)]; 3]; 3] = [
+    [&[(1, 0)], &[(1, 0)], &[(4, 0)]],
+    [&[(2, 0)], &[(2, 0)], &[(5, 0)]],
+    [&[(0, 0)], &[(4, 0), (5, 0)], &[(3, 0)]],
+];
+
+/// The values of the variables, in the order of INSTANCE_IDS and PRIVATE_IDS.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ZokratesDemoWitness<Scalar> {
+    pub instance: Vec<Scalar>,
+    pub private: Vec<Scalar>,
+}
+
+/// The circuit, with a witness to prove, or without for the setup.
+#[derive(Clone, Debug, Default)]
+pub struct ZokratesDemo<Scalar> {
+    pub witness: Option<ZokratesDemoWitness<Scalar>>,
+}
+
+impl<Scalar: PrimeField> Circuit<Scalar> for ZokratesDemo<Scalar> {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        if let Some(witness) = &self.witness {
+            if witness.instance.len() != INSTANCE_IDS.len() || witness.private.len() != PRIVATE_IDS.len() {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+        let value = |values: fn(&ZokratesDemoWitness<Scalar>) -> &[Scalar], i: usize| {
+            self.witness.as_ref().map(|w| values(w)[i]).ok_or(SynthesisError::AssignmentMissing)
+        };
+
+        let mut vars: Vec<Variable> = vec![CS::one()];
+
+        for (i, id) in INSTANCE_IDS.iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("public_{}", id));
+            let num = AllocatedNum::alloc(&mut cs, || value(|w| &w.instance, i))?;
+            num.inputize(&mut cs)?;
+            vars.push(num.get_variable());
+        }
+
+        for (i, id) in PRIVATE_IDS.iter().enumerate() {
+            let num = AllocatedNum::alloc(cs.namespace(|| format!("private_{}", id)), || value(|w| &w.private, i))?;
+            vars.push(num.get_variable());
+        }
+
+        let constants: Vec<Scalar> = CONSTANTS.iter().map(|le| read_scalar(le)).collect();
+        let lc = |terms: &[(usize, usize)]| {
+            terms.iter().fold(LinearCombination::zero(), |lc, (var, constant)| lc + (constants[*constant], vars[*var]))
+        };
+
+        for (i, [a, b, c]) in CONSTRAINTS.iter().enumerate() {
+            cs.namespace(|| format!("constraint_{}", i)).enforce(|| "", |_| lc(a), |_| lc(b), |_| lc(c));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Compress the instance variables of a circuit into a single public input.
+//!
+//! The instance variables become private. The only public input packs the first `Scalar::CAPACITY` bits
+//! of the SHA-256 hash of their values, each encoded in little-endian bytes as in zkInterface messages.
+//! Verifiers compute that input from the original values with `compress_public_inputs`.
+
+use bellman::{Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use bellman::gadgets::{boolean::Boolean, multipack, sha256};
+use ff::PrimeField;
+use zkinterface::Reader;
+use crate::zkif_backend::synthesize_zkif;
+
+
+/// ZKIFCircuit with its instance variables compressed into one public input.
+#[derive(Clone, Debug)]
+pub struct CompressedInputsCircuit<'a> {
+    pub reader: &'a Reader,
+}
+
+impl<'a, Scalar: PrimeField> Circuit<Scalar> for CompressedInputsCircuit<'a> {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let instance = synthesize_zkif(self.reader, cs, false)?;
+
+        let mut bits = vec![];
+        for (i, num) in instance.iter().enumerate() {
+            let mut le_bits = num.to_bits_le_strict(cs.namespace(|| format!("instance_bits_{}", i)))?;
+            le_bits.resize(repr_len::<Scalar>() * 8, Boolean::constant(false));
+            // Bytes in little-endian order, the bits of each byte from the most significant, as sha256 expects.
+            for byte in le_bits.chunks(8) {
+                bits.extend(byte.iter().rev().cloned());
+            }
+        }
+
+        let commitment = commitment_bits(cs, &bits)?;
+        multipack::pack_into_inputs(cs.namespace(|| "commitment"), &commitment)
+    }
+}
+
+/// Compute the public inputs of CompressedInputsCircuit from the values of the instance variables.
+pub fn compress_public_inputs<Scalar: PrimeField>(values: &[Scalar]) -> Result<Vec<Scalar>, SynthesisError> {
+    let bits: Vec<Boolean> = values.iter()
+        .flat_map(|value| multipack::bytes_to_bits(value.to_repr().as_ref()))
+        .map(Boolean::constant)
+        .collect();
+
+    let commitment = commitment_bits::<Scalar, _>(&mut ValuesCS(0), &bits)?;
+    let commitment = commitment.iter()
+        .map(|bit| bit.get_value().ok_or(SynthesisError::AssignmentMissing))
+        .collect::<Result<Vec<bool>, _>>()?;
+
+    Ok(multipack::compute_multipacking(&commitment))
+}
+
+/// The first CAPACITY bits of the SHA-256 hash of the bits.
+fn commitment_bits<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    bits: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError> {
+    let mut hash = sha256::sha256(cs.namespace(|| "sha256"), bits)?;
+    hash.truncate(Scalar::CAPACITY as usize);
+    Ok(hash)
+}
+
+fn repr_len<Scalar: PrimeField>() -> usize {
+    Scalar::Repr::default().as_ref().len()
+}
+
+/// A constraint system that only evaluates the gadgets, to compute values outside of a circuit.
+struct ValuesCS(usize);
+
+impl<Scalar: PrimeField> ConstraintSystem<Scalar> for ValuesCS {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+        where F: FnOnce() -> Result<Scalar, SynthesisError>, A: FnOnce() -> AR, AR: Into<String> {
+        self.0 += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.0)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+        where F: FnOnce() -> Result<Scalar, SynthesisError>, A: FnOnce() -> AR, AR: Into<String> {
+        self.alloc(annotation, f)
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, _: LA, _: LB, _: LC)
+        where A: FnOnce() -> AR, AR: Into<String>,
+              LA: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+              LB: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+              LC: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar> {}
+
+    fn push_namespace<NR, N>(&mut self, _: N) where NR: Into<String>, N: FnOnce() -> NR {}
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root { self }
+}
+
+
+#[test]
+fn test_compressed_inputs() -> zkinterface::Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::import::read_scalar;
+
+    let mut reader = Reader::new();
+    reader.read_file("src/tests/example.zkif")?;
+    let values: Vec<Scalar> = reader.instance_variables().unwrap().iter()
+        .map(|var| read_scalar(var.value)).collect();
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    CompressedInputsCircuit { reader: &reader }.synthesize(&mut cs)?;
+    assert!(cs.is_satisfied());
+    assert_eq!(cs.num_inputs(), 2); // The constant one and the commitment.
+
+    let inputs = compress_public_inputs(&values)?;
+    assert_eq!(inputs.len(), 1);
+    assert!(cs.verify(&inputs));
+
+    // Other values give another commitment.
+    let mut other = values.clone();
+    other[0] += Scalar::one();
+    assert!(!cs.verify(&compress_public_inputs(&other)?));
+    Ok(())
+}
@@ -0,0 +1,313 @@
+//! Read and write zkInterface files compressed with gzip or zstd.
+//!
+//! Compressed files are named like their uncompressed counterparts, with the extension of
+//! the compression format appended: `header.zkif.gz`, `constraints_0.zkif.zst`, etc.
+//! Readers detect the format from the magic bytes of the content, so compressed streams can
+//! also be piped into stdin.
+
+use std::ffi::OsStr;
+use std::fs::{File, create_dir_all, read_dir, remove_file};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use flate2::bufread::MultiGzDecoder;
+use zkinterface::{Result, Reader, Sink, CircuitHeader, ConstraintSystem, Witness};
+use zkinterface::consumers::workspace::iterate_stream;
+use crate::json::push_messages;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The file extension, including the .zkif part.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "zkif",
+            Compression::Gzip => "zkif.gz",
+            Compression::Zstd => "zkif.zst",
+        }
+    }
+
+    /// Recognize a zkInterface file by its extension, with or without compression.
+    pub fn from_path(path: &Path) -> Option<Compression> {
+        let name = path.file_name().and_then(OsStr::to_str)?;
+        [Compression::None, Compression::Gzip, Compression::Zstd].iter().cloned()
+            .find(|c| name.ends_with(&format!(".{}", c.extension())))
+    }
+
+    /// Recognize the compression format from the first bytes of the content.
+    pub fn from_magic(prefix: &[u8]) -> Compression {
+        if prefix.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if prefix.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wrap a stream to decompress it, if it starts with the magic bytes of a compression format.
+pub fn decompress<'r>(stream: impl Read + 'r) -> Result<Box<dyn Read + 'r>> {
+    let mut stream = BufReader::new(stream);
+
+    let compression = Compression::from_magic(stream.fill_buf()?);
+
+    Ok(match compression {
+        Compression::None => Box::new(stream),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(stream)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(stream)?),
+    })
+}
+
+/// Open a file and decompress it if necessary.
+pub fn open_file(path: impl AsRef<Path>) -> Result<Box<dyn Read>> {
+    decompress(File::open(path)?)
+}
+
+/// Read all messages from a file, compressed or not, in binary or JSON form, into a Reader.
+pub fn read_file(reader: &mut Reader, path: impl AsRef<Path>) -> Result<()> {
+    let mut buf = Vec::new();
+    open_file(path)?.read_to_end(&mut buf)?;
+    push_messages(reader, buf)
+}
+
+/// Iterate over the messages of a file, compressed or not, without loading it entirely.
+pub fn iterate_file(path: impl AsRef<Path>) -> Result<impl Iterator<Item=Vec<u8>>> {
+    Ok(iterate_stream(open_file(path)?))
+}
+
+/// List the zkInterface files, compressed or not, in the given files and directories.
+pub fn list_workspace_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut all_paths = vec![];
+
+    for path in paths {
+        if path.is_dir() {
+            let mut files = vec![];
+            for file in read_dir(path)? {
+                let file = file?.path();
+                if Compression::from_path(&file).is_some() {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            all_paths.extend(files);
+        } else {
+            all_paths.push(path.clone());
+        }
+    }
+    Ok(all_paths)
+}
+
+/// Read all messages from files and directories, compressed or not.
+pub fn read_workspace(paths: &[PathBuf]) -> Result<Reader> {
+    let mut reader = Reader::new();
+    for path in list_workspace_files(paths)? {
+        read_file(&mut reader, path)?;
+    }
+    Ok(reader)
+}
+
+/// Remove the zkInterface files, compressed or not, from a workspace.
+pub fn clean_workspace(workspace: impl AsRef<Path>) -> Result<()> {
+    for file in read_dir(workspace)? {
+        let file = file?.path();
+        if Compression::from_path(&file).is_some() {
+            remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Store messages into compressed files using conventional filenames inside of a workspace.
+/// Call finish() to complete the witness file and get any write error.
+pub struct CompressedWorkspaceSink {
+    pub workspace: PathBuf,
+    pub compression: Compression,
+    witness_file: Option<WitnessFile>,
+    cs_file_counter: u32,
+}
+
+/// The witness file, which may receive several Witness messages.
+enum WitnessFile {
+    None(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::stream::write::Encoder<File>),
+}
+
+impl WitnessFile {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            WitnessFile::None(file) => file,
+            WitnessFile::Gzip(encoder) => encoder,
+            WitnessFile::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            WitnessFile::None(mut file) => file.flush()?,
+            WitnessFile::Gzip(encoder) => { encoder.finish()?; }
+            WitnessFile::Zstd(encoder) => { encoder.finish()?; }
+        }
+        Ok(())
+    }
+}
+
+impl CompressedWorkspaceSink {
+    pub fn new(workspace: impl AsRef<Path>, compression: Compression) -> Result<Self> {
+        create_dir_all(workspace.as_ref())?;
+        Ok(CompressedWorkspaceSink {
+            workspace: workspace.as_ref().to_path_buf(),
+            compression,
+            witness_file: None,
+            cs_file_counter: 0,
+        })
+    }
+
+    /// Complete the witness file, if any.
+    pub fn finish(mut self) -> Result<()> {
+        match self.witness_file.take() {
+            Some(witness_file) => witness_file.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn file_path(&self, name: &str) -> PathBuf {
+        self.workspace.join(format!("{}.{}", name, self.compression.extension()))
+    }
+
+    fn write_file(&self, name: &str, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+        let file = File::create(self.file_path(name))?;
+
+        match self.compression {
+            Compression::None => {
+                let mut file = file;
+                write(&mut file)?;
+            }
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?;
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(file, ZSTD_LEVEL)?;
+                write(&mut encoder)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for CompressedWorkspaceSink {
+    fn push_header(&mut self, header: CircuitHeader) -> Result<()> {
+        self.write_file("header", |mut file| header.write_into(&mut file))
+    }
+
+    fn push_constraints(&mut self, cs: ConstraintSystem) -> Result<()> {
+        let name = format!("constraints_{}", self.cs_file_counter);
+        self.cs_file_counter += 1;
+        self.write_file(&name, |mut file| cs.write_into(&mut file))
+    }
+
+    fn push_witness(&mut self, witness: Witness) -> Result<()> {
+        if self.witness_file.is_none() {
+            let file = File::create(self.file_path("witness"))?;
+
+            self.witness_file = Some(match self.compression {
+                Compression::None => WitnessFile::None(file),
+                Compression::Gzip => WitnessFile::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+                Compression::Zstd => WitnessFile::Zstd(zstd::stream::write::Encoder::new(file, ZSTD_LEVEL)?),
+            });
+        }
+
+        witness.write_into(&mut self.witness_file.as_mut().unwrap().writer())
+    }
+}
+
+impl Drop for CompressedWorkspaceSink {
+    /// Complete the witness file if finish() was not called, ignoring errors.
+    fn drop(&mut self) {
+        if let Some(witness_file) = self.witness_file.take() {
+            let _ = witness_file.finish();
+        }
+    }
+}
+
+
+#[test]
+fn test_compressed_workspace() -> Result<()> {
+    use std::fs::{read, remove_dir_all};
+    use bls12_381::Scalar;
+    use bellman::ConstraintSystem;
+    use zkinterface::{Messages, Workspace};
+    use crate::zkif_cs::{ZkifCS, Target};
+
+    fn write_circuit(cs: &mut impl ConstraintSystem<Scalar>) {
+        let x = cs.alloc_input(|| "x", || Ok(Scalar::from(3))).unwrap();
+        for i in 0..10 {
+            let y = cs.alloc(|| "y", || Ok(Scalar::from(3 * i))).unwrap();
+            cs.enforce(|| "x * i = y",
+                       |lc| lc + x,
+                       |lc| lc + (Scalar::from(i), ZkifCS::<Scalar>::one()),
+                       |lc| lc + y);
+        }
+    }
+
+    let dir = PathBuf::from("local/test_compressed_workspace");
+    let _ = remove_dir_all(&dir);
+
+    let plain_dir = dir.join("none");
+    let mut cs = ZkifCS::<Scalar>::new(&plain_dir, Target::Prover);
+    cs.constraints_per_message = 4;
+    write_circuit(&mut cs);
+    cs.finish("test")?;
+    let expected = Workspace::from_dir(&plain_dir)?.read_all_messages();
+
+    for &compression in &[Compression::Gzip, Compression::Zstd] {
+        let compressed_dir = dir.join(compression.extension());
+        let paths = vec![compressed_dir.clone()];
+        let mut cs = ZkifCS::new_compressed(&compressed_dir, Target::Prover, compression)?;
+        cs.constraints_per_message = 4;
+        write_circuit(&mut cs);
+        cs.finish_compressed("test")?;
+
+        // The decompressed files are identical to the uncompressed files.
+        let files = list_workspace_files(&paths)?;
+        assert_eq!(files.len(), 5);
+        for file in &files {
+            assert_eq!(Compression::from_path(file), Some(compression));
+            let name = file.file_name().unwrap().to_str().unwrap().split('.').next().unwrap();
+            let mut decompressed = vec![];
+            open_file(file)?.read_to_end(&mut decompressed)?;
+            assert_eq!(decompressed, read(plain_dir.join(format!("{}.zkif", name)))?);
+        }
+
+        let reader = read_workspace(&paths)?;
+        assert_eq!(Messages::from(&reader), expected);
+
+        // A concatenation of compressed files can be streamed, like with cat *.zkif.gz | …
+        let mut concatenated = vec![];
+        for file in &files {
+            concatenated.extend(read(file)?);
+        }
+        let messages = iterate_stream(decompress(&concatenated[..])?).count();
+        assert_eq!(messages, 5);
+
+        clean_workspace(&compressed_dir)?;
+        assert_eq!(list_workspace_files(&paths)?.len(), 0);
+    }
+
+    Ok(())
+}
@@ -1,2 +0,0 @@
-pub mod demo;
-pub mod exec_zokrates;
@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::io::Write;
-use zkinterface::{Variables, BilinearConstraint};
-use bellman::{LinearCombination, Index};
+use std::path::{Path, PathBuf};
+use zkinterface::{Variables, BilinearConstraint, Result, Sink};
+use bellman::{Circuit, LinearCombination, Index, Variable};
 use ff::PrimeField;
+use crate::zkif_cs::{ZkifCS, Target, DEFAULT_CONSTRAINTS_PER_MESSAGE};
+use crate::compression::{Compression, CompressedWorkspaceSink, clean_workspace};
 
 
 pub fn to_zkif_constraint<Scalar: PrimeField>(
@@ -16,18 +21,51 @@ pub fn to_zkif_constraint<Scalar: PrimeField>(
     }
 }
 
+/// Like to_zkif_constraint, but keep the linear combinations as produced by bellman.
+pub fn to_zkif_constraint_raw<Scalar: PrimeField>(
+    a: LinearCombination<Scalar>,
+    b: LinearCombination<Scalar>,
+    c: LinearCombination<Scalar>,
+) -> BilinearConstraint {
+    BilinearConstraint {
+        linear_combination_a: to_zkif_lc_raw(a),
+        linear_combination_b: to_zkif_lc_raw(b),
+        linear_combination_c: to_zkif_lc_raw(c),
+    }
+}
+
+/// Convert a bellman LinearCombination to zkInterface terms in normal form:
+/// one term per variable, no zero coefficients, sorted by variable id.
 pub fn to_zkif_lc<Scalar: PrimeField>(
     lc: LinearCombination<Scalar>,
+) -> Variables {
+    let mut terms = BTreeMap::<u64, Scalar>::new();
+
+    for (var, coeff) in lc.as_ref() {
+        *terms.entry(to_zkid(var)).or_insert_with(Scalar::zero) += coeff;
+    }
+
+    let mut variable_ids = Vec::<u64>::new();
+    let mut coeffs = Vec::<u8>::new();
+
+    for (zkid, coeff) in terms {
+        if coeff.is_zero() { continue; }
+        variable_ids.push(zkid);
+        write_scalar(&coeff, &mut coeffs);
+    }
+
+    Variables { variable_ids, values: Some(coeffs) }
+}
+
+/// Convert a bellman LinearCombination to zkInterface terms as is, including duplicates and zeros.
+pub fn to_zkif_lc_raw<Scalar: PrimeField>(
+    lc: LinearCombination<Scalar>,
 ) -> Variables {
     let mut variable_ids = Vec::<u64>::new();
     let mut coeffs = Vec::<u8>::new();
 
     for (var, coeff) in lc.as_ref() {
-        let zkid = match var.get_unchecked() {
-            Index::Input(zkid) => zkid,
-            Index::Aux(zkid) => zkid,
-        };
-        variable_ids.push(zkid as u64);
+        variable_ids.push(to_zkid(var));
 
         write_scalar(coeff, &mut coeffs);
     }
@@ -35,6 +73,15 @@ pub fn to_zkif_lc<Scalar: PrimeField>(
     Variables { variable_ids, values: Some(coeffs) }
 }
 
+/// The zkInterface id of a bellman variable, as allocated by ZkifCS.
+pub fn to_zkid(var: &Variable) -> u64 {
+    let zkid = match var.get_unchecked() {
+        Index::Input(zkid) => zkid,
+        Index::Aux(zkid) => zkid,
+    };
+    zkid as u64
+}
+
 /// Convert bellman Fr to zkInterface little-endian bytes.
 /// TODO: Verify that Scalar::Repr is little-endian.
 pub fn write_scalar<Scalar: PrimeField>(
@@ -44,3 +91,275 @@ pub fn write_scalar<Scalar: PrimeField>(
     let repr = fr.to_repr();
     writer.write_all(repr.as_ref()).unwrap();
 }
+
+/// The largest element of the field, encoded for a zkInterface header.
+pub fn field_maximum<Scalar: PrimeField>() -> Vec<u8> {
+    let negative_one = Scalar::one().neg();
+    let mut field_maximum = Vec::<u8>::new();
+    write_scalar(&negative_one, &mut field_maximum);
+    field_maximum
+}
+
+
+/// Options for export_circuit.
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    /// The name of the circuit, stored in the header configuration.
+    pub name: String,
+    /// The maximum number of constraints per message, hence per file.
+    pub constraints_per_message: usize,
+    /// Keep linear combinations as produced by bellman, for debugging.
+    pub raw_linear_combinations: bool,
+    /// Compress the files written into a workspace.
+    pub compression: Compression,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            name: "circuit".to_string(),
+            constraints_per_message: DEFAULT_CONSTRAINTS_PER_MESSAGE,
+            raw_linear_combinations: false,
+            compression: Compression::None,
+        }
+    }
+}
+
+/// What export_circuit produced.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExportSummary {
+    pub num_instance_variables: usize,
+    pub num_private_variables: usize,
+    pub num_constraints: usize,
+    /// The files written, if exported into a workspace.
+    pub files: Vec<PathBuf>,
+}
+
+/// Synthesize any bellman circuit and write it into a zkInterface workspace.
+/// Previous zkInterface files in the workspace, compressed or not, are removed.
+pub fn export_circuit<Scalar: PrimeField, C: Circuit<Scalar>>(
+    circuit: C,
+    workspace: impl AsRef<Path>,
+    target: Target,
+    options: &ExportOptions,
+) -> Result<ExportSummary> {
+    let workspace = workspace.as_ref();
+    let sink = CompressedWorkspaceSink::new(workspace, options.compression)?;
+    clean_workspace(workspace)?;
+
+    let (summary, num_constraint_messages, sink) = export_into(circuit, sink, target, options)?;
+    sink.finish()?;
+
+    let file_path = |name: &str| workspace.join(format!("{}.{}", name, options.compression.extension()));
+    let mut files = vec![file_path("header")];
+    for i in 0..num_constraint_messages {
+        files.push(file_path(&format!("constraints_{}", i)));
+    }
+    if target == Target::Prover {
+        files.push(file_path("witness"));
+    }
+
+    Ok(ExportSummary { files, ..summary })
+}
+
+/// Synthesize any bellman circuit and write the messages into a Sink.
+pub fn export_circuit_to_sink<Scalar: PrimeField, C: Circuit<Scalar>, S: Sink>(
+    circuit: C,
+    sink: S,
+    target: Target,
+    options: &ExportOptions,
+) -> Result<ExportSummary> {
+    let (summary, _, _) = export_into(circuit, sink, target, options)?;
+    Ok(summary)
+}
+
+fn export_into<Scalar: PrimeField, C: Circuit<Scalar>, S: Sink>(
+    circuit: C,
+    sink: S,
+    target: Target,
+    options: &ExportOptions,
+) -> Result<(ExportSummary, usize, S)> {
+    let mut cs = ZkifCS::<Scalar, S>::from_sink(sink, target);
+    cs.constraints_per_message = options.constraints_per_message;
+    cs.raw_linear_combinations = options.raw_linear_combinations;
+
+    circuit.synthesize(&mut cs)?;
+
+    let summary = ExportSummary {
+        num_instance_variables: cs.num_instance_variables(),
+        num_private_variables: cs.num_private_variables(),
+        num_constraints: cs.num_constraints(),
+        files: vec![],
+    };
+    let num_constraint_messages = cs.num_constraint_messages();
+
+    let sink = cs.finish_into_sink(&options.name)?;
+    Ok((summary, num_constraint_messages, sink))
+}
+
+
+const EXPORT_USAGE: &str = "Export a bellman circuit to zkInterface.
+
+    <program> [--target prover|verifier|preprocessing] [--name <name>] [--constraints-per-message <n>] [--raw] [--gzip|--zstd] [<workspace>]
+
+The target defaults to prover, and the workspace to the current directory.
+";
+
+/// A command-line entry point for example binaries that export a circuit.
+pub fn export_main<Scalar: PrimeField, C: Circuit<Scalar>>(circuit: C) -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|a| &a[..]).collect();
+
+    let mut target = Target::Prover;
+    let mut options = ExportOptions::default();
+    let mut workspace = env::current_dir()?;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--target" => {
+                target = match args.next() {
+                    Some("prover") => Target::Prover,
+                    Some("verifier") => Target::Verifier,
+                    Some("preprocessing") => Target::Preprocessing,
+                    other => {
+                        eprintln!("{}", EXPORT_USAGE);
+                        return Err(format!("Unknown target {:?}", other).into());
+                    }
+                };
+            }
+            "--name" => {
+                options.name = args.next().ok_or("Missing value for --name")?.to_string();
+            }
+            "--constraints-per-message" => {
+                options.constraints_per_message = args.next().ok_or("Missing value for --constraints-per-message")?.parse()?;
+            }
+            "--raw" => options.raw_linear_combinations = true,
+            "--gzip" => options.compression = Compression::Gzip,
+            "--zstd" => options.compression = Compression::Zstd,
+            "-h" | "--help" => {
+                eprintln!("{}", EXPORT_USAGE);
+                return Ok(());
+            }
+            _ => workspace = PathBuf::from(arg),
+        }
+    }
+
+    let summary = export_circuit(circuit, &workspace, target, &options)?;
+
+    eprintln!("Exported {} instance variables, {} private variables, {} constraints.",
+              summary.num_instance_variables, summary.num_private_variables, summary.num_constraints);
+    for file in &summary.files {
+        eprintln!("Written {}", file.display());
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_export_circuit() -> Result<()> {
+    use std::fs::remove_dir_all;
+    use bellman::{ConstraintSystem, SynthesisError, gadgets::num::AllocatedNum};
+    use bls12_381::Scalar;
+    use zkinterface::{Reader, Workspace};
+    use zkinterface::consumers::simulator::Simulator;
+    use crate::zkif_backend::validate;
+
+    struct SquaresCircuit {
+        n: u64,
+    }
+
+    impl Circuit<Scalar> for SquaresCircuit {
+        fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> std::result::Result<(), SynthesisError> {
+            for i in 0..self.n {
+                let x = AllocatedNum::alloc(cs.namespace(|| format!("x_{}", i)), || Ok(Scalar::from(i)))?;
+                let xx = x.square(cs.namespace(|| format!("xx_{}", i)))?;
+                xx.inputize(cs.namespace(|| format!("input_{}", i)))?;
+            }
+            Ok(())
+        }
+    }
+
+    let dir = Path::new("local/test_export_circuit/");
+    let _ = remove_dir_all(dir);
+
+    let options = ExportOptions {
+        name: "squares".to_string(),
+        constraints_per_message: 4,
+        raw_linear_combinations: false,
+        compression: Compression::None,
+    };
+    let summary = export_circuit(SquaresCircuit { n: 5 }, dir, Target::Prover, &options)?;
+
+    // Each square allocates x and xx, and inputize copies xx into an instance variable.
+    assert_eq!(summary.num_instance_variables, 5);
+    assert_eq!(summary.num_private_variables, 10);
+    assert_eq!(summary.num_constraints, 10);
+    assert_eq!(summary.files, vec![
+        dir.join("header.zkif"),
+        dir.join("constraints_0.zkif"),
+        dir.join("constraints_1.zkif"),
+        dir.join("constraints_2.zkif"),
+        dir.join("witness.zkif"),
+    ]);
+
+    let ws = Workspace::from_dir(dir)?;
+    let mut simulator = Simulator::default();
+    for msg in ws.iter_messages() {
+        simulator.ingest_message(&msg);
+    }
+    assert_eq!(simulator.get_violations().len(), 0);
+
+    let mut reader = Reader::new();
+    for file in &summary.files {
+        reader.read_file(file)?;
+    }
+    validate::<Scalar>(&reader, false)?;
+
+    // A smaller export into the same workspace replaces the previous files.
+    let summary = export_circuit(SquaresCircuit { n: 1 }, dir, Target::Preprocessing, &options)?;
+    assert_eq!(summary.files, vec![dir.join("header.zkif"), dir.join("constraints_0.zkif")]);
+    assert_eq!(Workspace::from_dir(dir)?, Workspace::from_filenames(summary.files));
+
+    Ok(())
+}
+
+
+#[test]
+fn test_to_zkif_lc() {
+    use bls12_381::Scalar;
+
+    let x = Variable::new_unchecked(Index::Aux(2));
+    let y = Variable::new_unchecked(Index::Input(1));
+    let one = Variable::new_unchecked(Index::Input(0));
+
+    let two = Scalar::from(2);
+    let lc = LinearCombination::zero() + (two, x) + y + (two, one) - (two, one) + x;
+
+    let encode = |values: &[u64]| {
+        let mut encoded = vec![];
+        for v in values { write_scalar(&Scalar::from(*v), &mut encoded); }
+        Some(encoded)
+    };
+
+    // Merge x, drop the constant one, sort by id.
+    assert_eq!(to_zkif_lc(lc.clone()), Variables {
+        variable_ids: vec![1, 2],
+        values: encode(&[1, 3]),
+    });
+
+    let minus_two = Scalar::zero() - two;
+    let mut raw_values = encode(&[2, 1, 2]).unwrap();
+    write_scalar(&minus_two, &mut raw_values);
+    raw_values.extend(encode(&[1]).unwrap());
+
+    assert_eq!(to_zkif_lc_raw(lc), Variables {
+        variable_ids: vec![2, 1, 0, 0, 2],
+        values: Some(raw_values),
+    });
+
+    assert_eq!(to_zkif_lc(LinearCombination::<Scalar>::zero() + x - x), Variables {
+        variable_ids: vec![],
+        values: Some(vec![]),
+    });
+}
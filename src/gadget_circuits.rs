@@ -0,0 +1,276 @@
+//! Reference circuits of the gadgets built into bellman, to export as zkInterface fixtures.
+//!
+//! The inputs of each gadget are private variables, and its outputs are public inputs,
+//! packed with multipack when they are bits.
+
+use std::env;
+use std::path::PathBuf;
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use bellman::gadgets::boolean::{AllocatedBit, Boolean};
+use bellman::gadgets::{blake2s, lookup, multieq::MultiEq, multipack, sha256, uint32::UInt32};
+use ff::PrimeField;
+use zkinterface::Result;
+use crate::compression::Compression;
+use crate::export::{export_circuit, ExportOptions};
+use crate::zkif_cs::Target;
+
+/// The names of the gadgets, their inputs, and the default inputs.
+pub const GADGETS: &[(&str, &str, &str)] = &[
+    ("sha256", "<hex message>", "616263"),
+    ("blake2s", "<hex message>", "616263"),
+    ("and", "<hex a> <hex b>", "0f 35"),
+    ("xor", "<hex a> <hex b>", "0f 35"),
+    ("uint32-add", "<u32> <u32>…", "1 2 4294967295"),
+    ("multipack", "<hex bytes>", "616263"),
+    ("lookup", "<index 0-7>", "5"),
+];
+
+/// A gadget built into bellman, with its inputs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuiltinGadget {
+    /// SHA-256 of a message, with bits most significant first in each byte.
+    Sha256(Vec<u8>),
+    /// BLAKE2s of a message, with bits least significant first in each byte, and a zero personalization.
+    Blake2s(Vec<u8>),
+    /// Bitwise AND of two byte strings.
+    And(Vec<u8>, Vec<u8>),
+    /// Bitwise XOR of two byte strings.
+    Xor(Vec<u8>, Vec<u8>),
+    /// Sum of 32-bit integers, modulo 2^32.
+    UInt32Add(Vec<u32>),
+    /// Pack the bits of bytes into public inputs.
+    Multipack(Vec<u8>),
+    /// Look up the coordinates (i, i² + 1) at an index i from a table of 8 entries.
+    Lookup(u8),
+}
+
+impl BuiltinGadget {
+    /// Parse the inputs of a gadget by name. Without inputs, use the defaults from GADGETS.
+    pub fn parse(name: &str, inputs: &[&str]) -> Result<Self> {
+        let (_, _, defaults) = GADGETS.iter().find(|(n, _, _)| *n == name)
+            .ok_or_else(|| format!("Unknown gadget {}", name))?;
+        let inputs: Vec<&str> = if inputs.is_empty() { defaults.split(' ').collect() } else { inputs.to_vec() };
+
+        let expect = |n: usize| -> Result<()> {
+            if inputs.len() != n {
+                return Err(format!("The gadget {} takes {} inputs, got {}.", name, n, inputs.len()).into());
+            }
+            Ok(())
+        };
+        let two_strings = || -> Result<(Vec<u8>, Vec<u8>)> {
+            expect(2)?;
+            let (a, b) = (parse_hex(inputs[0])?, parse_hex(inputs[1])?);
+            if a.len() != b.len() {
+                return Err("The inputs must have the same length.".into());
+            }
+            Ok((a, b))
+        };
+
+        Ok(match name {
+            "sha256" => { expect(1)?; BuiltinGadget::Sha256(parse_hex(inputs[0])?) }
+            "blake2s" => { expect(1)?; BuiltinGadget::Blake2s(parse_hex(inputs[0])?) }
+            "and" => { let (a, b) = two_strings()?; BuiltinGadget::And(a, b) }
+            "xor" => { let (a, b) = two_strings()?; BuiltinGadget::Xor(a, b) }
+            "uint32-add" => {
+                if inputs.len() < 2 {
+                    return Err("The gadget uint32-add takes at least 2 inputs.".into());
+                }
+                BuiltinGadget::UInt32Add(inputs.iter().map(|s| s.parse()).collect::<std::result::Result<_, _>>()?)
+            }
+            "multipack" => { expect(1)?; BuiltinGadget::Multipack(parse_hex(inputs[0])?) }
+            "lookup" => {
+                expect(1)?;
+                let index = inputs[0].parse()?;
+                if index >= 8 {
+                    return Err("The lookup index must be between 0 and 7.".into());
+                }
+                BuiltinGadget::Lookup(index)
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+#[allow(clippy::manual_is_multiple_of)]
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex {}", hex).into());
+    }
+    if hex.len() % 2 != 0 {
+        return Err(format!("Odd number of hex digits in {}", hex).into());
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|err| format!("Invalid hex {}: {}", hex, err).into()))
+        .collect()
+}
+
+fn alloc_bits<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    name: &str,
+    bits: &[bool],
+) -> std::result::Result<Vec<Boolean>, SynthesisError> {
+    bits.iter().enumerate().map(|(i, bit)|
+        Ok(Boolean::from(AllocatedBit::alloc(cs.namespace(|| format!("{}_{}", name, i)), Some(*bit))?))
+    ).collect()
+}
+
+impl<Scalar: PrimeField> Circuit<Scalar> for BuiltinGadget {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> std::result::Result<(), SynthesisError> {
+        let is_and = matches!(self, BuiltinGadget::And(_, _));
+
+        let output_bits = match self {
+            BuiltinGadget::Sha256(message) => {
+                let input = alloc_bits(cs, "message", &multipack::bytes_to_bits(&message))?;
+                sha256::sha256(cs.namespace(|| "sha256"), &input)?
+            }
+
+            BuiltinGadget::Blake2s(message) => {
+                let input = alloc_bits(cs, "message", &multipack::bytes_to_bits_le(&message))?;
+                blake2s::blake2s(cs.namespace(|| "blake2s"), &input, &[0; 8])?
+            }
+
+            BuiltinGadget::And(a, b) | BuiltinGadget::Xor(a, b) => {
+                let a = alloc_bits(cs, "a", &multipack::bytes_to_bits(&a))?;
+                let b = alloc_bits(cs, "b", &multipack::bytes_to_bits(&b))?;
+                a.iter().zip(&b).enumerate().map(|(i, (a, b))| {
+                    let cs = cs.namespace(|| format!("bit_{}", i));
+                    if is_and { Boolean::and(cs, a, b) } else { Boolean::xor(cs, a, b) }
+                }).collect::<std::result::Result<_, _>>()?
+            }
+
+            BuiltinGadget::UInt32Add(values) => {
+                let operands = values.iter().enumerate().map(|(i, v)|
+                    UInt32::alloc(cs.namespace(|| format!("operand_{}", i)), Some(*v))
+                ).collect::<std::result::Result<Vec<_>, _>>()?;
+                let mut cs = MultiEq::new(cs.namespace(|| "addmany"));
+                UInt32::addmany(cs.namespace(|| "sum"), &operands)?.into_bits()
+            }
+
+            BuiltinGadget::Multipack(bytes) => {
+                alloc_bits(cs, "bits", &multipack::bytes_to_bits_le(&bytes))?
+            }
+
+            BuiltinGadget::Lookup(index) => {
+                let index_bits: Vec<bool> = (0..3).map(|i| (index >> i) & 1 == 1).collect();
+                let bits = alloc_bits(cs, "index", &index_bits)?;
+                let table: Vec<(Scalar, Scalar)> = (0..8u64).map(|i| (Scalar::from(i), Scalar::from(i * i + 1))).collect();
+                let (x, y) = lookup::lookup3_xy(cs.namespace(|| "lookup"), &bits, &table)?;
+                x.inputize(cs.namespace(|| "x"))?;
+                y.inputize(cs.namespace(|| "y"))?;
+                return Ok(());
+            }
+        };
+
+        multipack::pack_into_inputs(cs.namespace(|| "outputs"), &output_bits)
+    }
+}
+
+const GADGET_USAGE: &str = "Export the circuit of a gadget built into bellman.
+
+    zkif_bellman gadget <name> [--inputs <input>…] [--target prover|preprocessing] [--gzip|--zstd] --out <workspace>
+
+Without --inputs, use example inputs. Gadgets:
+";
+
+fn gadget_usage() -> String {
+    let mut usage = GADGET_USAGE.to_string();
+    for (name, inputs, defaults) in GADGETS {
+        usage += &format!("\n    {:12} {:20} (default: {})", name, inputs, defaults);
+    }
+    usage
+}
+
+/// The command `zkif_bellman gadget`, given the arguments after the command.
+pub fn gadget_main<Scalar: PrimeField>(args: &[&str]) -> Result<()> {
+    let mut name = None;
+    let mut inputs = vec![];
+    let mut target = Target::Prover;
+    let mut options = ExportOptions::default();
+    let mut workspace = None;
+
+    let mut args = args.iter().cloned().peekable();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--inputs" => {
+                while let Some(input) = args.peek() {
+                    if input.starts_with("--") { break; }
+                    inputs.push(*input);
+                    args.next();
+                }
+            }
+            "--target" => {
+                target = match args.next() {
+                    Some("prover") => Target::Prover,
+                    Some("preprocessing") => Target::Preprocessing,
+                    other => return Err(format!("Unknown target {:?}", other).into()),
+                };
+            }
+            "--out" => workspace = Some(PathBuf::from(args.next().ok_or("Missing value for --out")?)),
+            "--gzip" => options.compression = Compression::Gzip,
+            "--zstd" => options.compression = Compression::Zstd,
+            "-h" | "--help" => {
+                eprintln!("{}", gadget_usage());
+                return Ok(());
+            }
+            _ if name.is_none() => name = Some(arg),
+            _ => return Err(format!("Unexpected argument {}", arg).into()),
+        }
+    }
+
+    let name = match name {
+        Some(name) => name,
+        None => {
+            eprintln!("{}", gadget_usage());
+            return Err("Missing gadget name.".into());
+        }
+    };
+    let gadget = BuiltinGadget::parse(name, &inputs)?;
+    options.name = name.to_string();
+    let workspace = workspace.map_or_else(env::current_dir, Ok)?;
+
+    let summary = export_circuit::<Scalar, _>(gadget, &workspace, target, &options)?;
+
+    eprintln!("Exported {} instance variables, {} private variables, {} constraints.",
+              summary.num_instance_variables, summary.num_private_variables, summary.num_constraints);
+    for file in &summary.files {
+        eprintln!("Written {}", file.display());
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_gadget_circuits() -> Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::compression::read_workspace;
+    use crate::zkif_backend::validate;
+
+    for (name, _, _) in GADGETS {
+        let gadget = BuiltinGadget::parse(name, &[])?;
+        let mut cs = TestConstraintSystem::<Scalar>::new();
+        gadget.clone().synthesize(&mut cs)?;
+        assert!(cs.is_satisfied(), "{} is not satisfied", name);
+    }
+
+    // The packed bits of "abc", and the lookup.
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    BuiltinGadget::Multipack(b"abc".to_vec()).synthesize(&mut cs)?;
+    assert_eq!(cs.get_input(1, "outputs/input 0"), Scalar::from(0x636261));
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    BuiltinGadget::parse("lookup", &["3"])?.synthesize(&mut cs)?;
+    assert_eq!(cs.get_input(2, "y/input variable"), Scalar::from(10));
+
+    // Export through the command.
+    let dir = "local/test_gadget_circuits";
+    gadget_main::<Scalar>(&["xor", "--inputs", "ff00", "0f0f", "--out", dir])?;
+    validate::<Scalar>(&read_workspace(&[PathBuf::from(dir)])?, false)?;
+    gadget_main::<Scalar>(&["sha256", "--target", "preprocessing", "--gzip", "--out", dir])?;
+
+    assert!(BuiltinGadget::parse("and", &["ff", "0f0f"]).is_err());
+    assert!(BuiltinGadget::parse("lookup", &["8"]).is_err());
+    assert!(BuiltinGadget::parse("md5", &[]).is_err());
+    assert!(BuiltinGadget::parse("sha256", &["é0"]).is_err());
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! Gadgets registered by name, and a dispatcher to call them through call_gadget.
+
+use std::collections::BTreeMap;
+use zkinterface::{CircuitHeader, KeyValue, Reader, Result};
+use crate::import::{GadgetCallOptions, read_call};
+use crate::process_gadget::ProcessGadget;
+use crate::recorded_gadget::RecordedGadget;
+
+/// The configuration key that names the gadget function to call.
+pub const FUNCTION_KEY: &str = "function";
+
+/// Anything that responds to zkInterface gadget calls.
+pub trait ZkifGadget {
+    /// Respond to a call message with the messages of the gadget: header with outputs, constraints, witness.
+    fn call(&self, call_msg: &[u8]) -> Result<Reader>;
+}
+
+impl<F: Fn(&[u8]) -> Result<Reader>> ZkifGadget for F {
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        self(call_msg)
+    }
+}
+
+impl ZkifGadget for ProcessGadget {
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        self.exec(call_msg)
+    }
+}
+
+impl<F: Fn(&[u8]) -> Result<Reader>> ZkifGadget for RecordedGadget<F> {
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        self.exec(call_msg)
+    }
+}
+
+/// Gadgets by function name.
+///
+/// # Example
+/// ```no_run
+/// # use zkinterface_bellman::gadget_registry::{GadgetRegistry, function_options};
+/// # use zkinterface_bellman::process_gadget::ProcessGadget;
+/// let mut registry = GadgetRegistry::new();
+/// registry.register("sha256", ProcessGadget::new("sha256_gadget"));
+/// // call_gadget_with_options(cs, &inputs, &registry.exec_fn(), &function_options("sha256"))
+/// ```
+#[derive(Default)]
+pub struct GadgetRegistry {
+    gadgets: BTreeMap<String, Box<dyn ZkifGadget>>,
+}
+
+impl GadgetRegistry {
+    pub fn new() -> Self { GadgetRegistry::default() }
+
+    /// Register a gadget under a function name, replacing any previous gadget of that name.
+    pub fn register(&mut self, function: &str, gadget: impl ZkifGadget + 'static) {
+        self.gadgets.insert(function.to_string(), Box::new(gadget));
+    }
+
+    pub fn get(&self, function: &str) -> Option<&dyn ZkifGadget> {
+        self.gadgets.get(function).map(|g| g.as_ref())
+    }
+
+    /// The registered function names, in order.
+    pub fn functions(&self) -> Vec<&str> {
+        self.gadgets.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// The function to give to call_gadget.
+    pub fn exec_fn(&self) -> impl Fn(&[u8]) -> Result<Reader> + '_ {
+        move |call_msg| self.call(call_msg)
+    }
+}
+
+impl ZkifGadget for GadgetRegistry {
+    /// Route the call to the gadget named by the `function` key of its configuration.
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        let (header, _) = read_call(call_msg)?;
+        let function = function_name(&header)
+            .ok_or_else(|| format!("The call has no '{}' in its configuration.", FUNCTION_KEY))?;
+
+        let gadget = self.get(function).ok_or_else(|| format!(
+            "Unknown gadget function '{}' (registered: {}).", function, self.functions().join(", ")))?;
+
+        gadget.call(call_msg)
+    }
+}
+
+/// The name of the function called, from the configuration of a call.
+pub fn function_name(call: &CircuitHeader) -> Option<&str> {
+    call.configuration.as_ref()?.iter()
+        .find(|kv| kv.key == FUNCTION_KEY)?
+        .text.as_deref()
+}
+
+/// Options to call a function of a registry.
+pub fn function_options(function: &str) -> GadgetCallOptions {
+    GadgetCallOptions {
+        configuration: Some(vec![KeyValue::from((FUNCTION_KEY, function))]),
+        ..GadgetCallOptions::default()
+    }
+}
+
+
+#[test]
+fn test_gadget_registry() -> Result<()> {
+    use bellman::ConstraintSystem;
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::{call_gadget, call_gadget_with_options, example_gadget};
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+
+    let mut registry = GadgetRegistry::new();
+    registry.register("squares", example_gadget);
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "squares"), &inputs, &registry.exec_fn(), &function_options("squares"))?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+
+    // Swap the implementation, with the same circuit code.
+    registry.register("squares", |call_msg: &[u8]| {
+        let (header, _) = read_call(call_msg)?;
+        assert_eq!(function_name(&header), Some("squares"));
+        example_gadget(call_msg)
+    });
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "swapped"), &inputs, &registry.exec_fn(), &function_options("squares"))?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+    assert!(cs.is_satisfied());
+
+    // Unknown or missing functions.
+    match call_gadget_with_options(&mut cs.namespace(|| "unknown"), &inputs, &registry.exec_fn(), &function_options("cubes")) {
+        Err(err) => assert!(err.to_string().contains("Unknown gadget function 'cubes' (registered: squares)")),
+        Ok(_) => panic!("Should refuse an unknown function"),
+    }
+    assert!(call_gadget(&mut cs.namespace(|| "missing"), &inputs, &registry.exec_fn()).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,280 @@
+//! Serve bellman gadgets to other systems through zkInterface calls.
+//!
+//! A call gives the ids of the inputs and optionally their values. The gadget is synthesized through
+//! ZkifCS with the inputs at those ids and new variables from the free_variable_id of the call.
+//! The response contains the constraints, the witness of the local variables, and a header with the outputs.
+
+use std::marker::PhantomData;
+use bellman::{ConstraintSystem, SynthesisError, Variable};
+use bellman::gadgets::boolean::{AllocatedBit, Boolean};
+use bellman::gadgets::num::AllocatedNum;
+use bellman::gadgets::{blake2s, sha256};
+use ff::PrimeField;
+use zkinterface::{CircuitHeader, Reader, Result, Sink, ConstraintSystem as ZkifConstraints, Witness};
+use crate::gadget_registry::{GadgetRegistry, ZkifGadget};
+use crate::import::{read_call, check_field_maximum, try_read_scalar};
+use crate::zkif_cs::{ZkifCS, Target};
+
+/// The configuration key of the personalization of BLAKE2s, 8 characters.
+pub const PERSONALIZATION_KEY: &str = "personalization";
+
+/// Collect messages into a buffer.
+#[derive(Default)]
+pub struct BufferSink {
+    pub buf: Vec<u8>,
+    /// Drop the constraints, when only a witness is requested.
+    pub skip_constraints: bool,
+}
+
+impl Sink for BufferSink {
+    fn push_header(&mut self, header: CircuitHeader) -> Result<()> {
+        header.write_into(&mut self.buf)
+    }
+
+    fn push_constraints(&mut self, cs: ZkifConstraints) -> Result<()> {
+        if self.skip_constraints { return Ok(()); }
+        cs.write_into(&mut self.buf)
+    }
+
+    fn push_witness(&mut self, witness: Witness) -> Result<()> {
+        witness.write_into(&mut self.buf)
+    }
+}
+
+/// The constraint system in which served gadgets are synthesized.
+pub type GadgetCS<Scalar> = ZkifCS<Scalar, BufferSink>;
+
+/// A bellman gadget that responds to zkInterface calls.
+///
+/// The synthesize function receives the values of the inputs, if any, and the call for its configuration.
+/// It must first allocate one variable per input, with alloc(), for instance with alloc_input_nums
+/// or alloc_input_bits. It returns the output variables.
+pub struct BellmanGadget<Scalar, F> {
+    synthesize: F,
+    phantom: PhantomData<Scalar>,
+}
+
+impl<Scalar, F> BellmanGadget<Scalar, F>
+    where Scalar: PrimeField,
+          F: Fn(&mut GadgetCS<Scalar>, &[Option<Scalar>], &CircuitHeader) -> Result<Vec<Variable>>
+{
+    pub fn new(synthesize: F) -> Self {
+        BellmanGadget { synthesize, phantom: PhantomData }
+    }
+}
+
+impl<Scalar, F> ZkifGadget for BellmanGadget<Scalar, F>
+    where Scalar: PrimeField,
+          F: Fn(&mut GadgetCS<Scalar>, &[Option<Scalar>], &CircuitHeader) -> Result<Vec<Variable>>
+{
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        let (call, command) = read_call(call_msg)?;
+        if let Some(field_maximum) = &call.field_maximum {
+            check_field_maximum::<Scalar>(field_maximum)?;
+        }
+
+        let witness_generation = command.witness_generation && call.instance_variables.values.is_some();
+        let values = if witness_generation {
+            call.instance_variables.get_variables().iter()
+                .map(|var| try_read_scalar(var.value).map(Some))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            vec![None; call.instance_variables.variable_ids.len()]
+        };
+
+        let target = if witness_generation { Target::Prover } else { Target::Preprocessing };
+        let sink = BufferSink {
+            buf: vec![],
+            skip_constraints: !command.constraints_generation,
+        };
+        let mut cs = ZkifCS::from_call(sink, target, &call);
+
+        let outputs = (self.synthesize)(&mut cs, &values, &call)?;
+        let sink = cs.finish_gadget(&outputs)?;
+
+        let mut reader = Reader::new();
+        reader.push_message(sink.buf)?;
+        Ok(reader)
+    }
+}
+
+/// Allocate the inputs as numbers.
+pub fn alloc_input_nums<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    values: &[Option<Scalar>],
+) -> std::result::Result<Vec<AllocatedNum<Scalar>>, SynthesisError> {
+    values.iter().enumerate().map(|(i, value)|
+        AllocatedNum::alloc(cs.namespace(|| format!("input_{}", i)), || value.ok_or(SynthesisError::AssignmentMissing))
+    ).collect()
+}
+
+/// Allocate the inputs as bits, with a booleanity constraint each.
+pub fn alloc_input_bits<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    values: &[Option<Scalar>],
+) -> Result<Vec<Boolean>> {
+    let mut bits = vec![];
+    for (i, value) in values.iter().enumerate() {
+        let bit = match value {
+            None => None,
+            Some(v) if v.is_zero() => Some(false),
+            Some(v) if *v == Scalar::one() => Some(true),
+            Some(_) => return Err(format!("Input {} is not a bit.", i).into()),
+        };
+        bits.push(Boolean::from(AllocatedBit::alloc(cs.namespace(|| format!("input_{}", i)), bit)?));
+    }
+    Ok(bits)
+}
+
+/// The variables of output bits. A bit that is negated or constant is copied into a new variable.
+pub fn output_bits<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    bits: &[Boolean],
+) -> Result<Vec<Variable>> {
+    let mut vars = vec![];
+    for (i, bit) in bits.iter().enumerate() {
+        let var = match bit {
+            Boolean::Is(bit) => bit.get_variable(),
+            _ => {
+                let mut cs = cs.namespace(|| format!("output_{}", i));
+                let num = AllocatedNum::alloc(cs.namespace(|| "copy"), || {
+                    let value = bit.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                    Ok(if value { Scalar::one() } else { Scalar::zero() })
+                })?;
+                cs.enforce(|| "copy = bit",
+                           |lc| lc + num.get_variable(),
+                           |lc| lc + CS::one(),
+                           |_| bit.lc(CS::one(), Scalar::one()));
+                num.get_variable()
+            }
+        };
+        vars.push(var);
+    }
+    Ok(vars)
+}
+
+#[allow(clippy::manual_is_multiple_of)] // is_multiple_of is newer than the minimum Rust version.
+fn check_bytes(bits: &[Boolean]) -> Result<()> {
+    if bits.len() % 8 != 0 {
+        return Err(format!("The input must be a whole number of bytes ({} bits).", bits.len()).into());
+    }
+    Ok(())
+}
+
+/// SHA-256 of the input bits. Bits are ordered as in bellman: most significant bit first in each byte.
+pub fn sha256_gadget<Scalar: PrimeField>(
+    cs: &mut GadgetCS<Scalar>,
+    values: &[Option<Scalar>],
+    _call: &CircuitHeader,
+) -> Result<Vec<Variable>> {
+    let input = alloc_input_bits(cs, values)?;
+    check_bytes(&input)?;
+    let hash = sha256::sha256(cs.namespace(|| "sha256"), &input)?;
+    output_bits(cs, &hash)
+}
+
+/// BLAKE2s of the input bits. Bits are ordered as in bellman: least significant bit first in each byte.
+/// The personalization is read from the configuration, and defaults to 8 zero bytes.
+pub fn blake2s_gadget<Scalar: PrimeField>(
+    cs: &mut GadgetCS<Scalar>,
+    values: &[Option<Scalar>],
+    call: &CircuitHeader,
+) -> Result<Vec<Variable>> {
+    let personalization = call.configuration.iter().flatten()
+        .find(|kv| kv.key == PERSONALIZATION_KEY)
+        .map(|kv| kv.text.clone().unwrap_or_default().into_bytes())
+        .unwrap_or_else(|| vec![0; 8]);
+    if personalization.len() != 8 {
+        return Err("The personalization of BLAKE2s must be 8 bytes.".into());
+    }
+
+    let input = alloc_input_bits(cs, values)?;
+    check_bytes(&input)?;
+    let hash = blake2s::blake2s(cs.namespace(|| "blake2s"), &input, &personalization)?;
+    output_bits(cs, &hash)
+}
+
+/// A registry of the bellman gadgets served: "sha256" and "blake2s".
+pub fn bellman_gadgets<Scalar: PrimeField>() -> GadgetRegistry {
+    let mut registry = GadgetRegistry::new();
+    registry.register("sha256", BellmanGadget::new(sha256_gadget::<Scalar>));
+    registry.register("blake2s", BellmanGadget::new(blake2s_gadget::<Scalar>));
+    registry
+}
+
+
+#[test]
+fn test_serve_bellman_gadgets() -> Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use zkinterface::KeyValue;
+    use crate::gadget_registry::function_options;
+    use crate::import::call_gadget_with_options;
+
+    let message = b"zk";
+    let bits: Vec<bool> = message.iter().flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1)).collect();
+
+    // The expected hash, from the gadget used directly.
+    let mut direct_cs = TestConstraintSystem::<Scalar>::new();
+    let direct_input: Vec<Boolean> = bits.iter().map(|b| Boolean::constant(*b)).collect();
+    let direct_input: Vec<Boolean> = direct_input.iter().enumerate().map(|(i, b)|
+        Boolean::from(AllocatedBit::alloc(direct_cs.namespace(|| format!("bit_{}", i)), b.get_value()).unwrap())
+    ).collect();
+    let expected = sha256::sha256(direct_cs.namespace(|| "sha256"), &direct_input)?;
+
+    let registry = bellman_gadgets::<Scalar>();
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = bits.iter().enumerate().map(|(i, b)|
+        AllocatedNum::alloc(cs.namespace(|| format!("bit_{}", i)), || Ok(if *b { Scalar::one() } else { Scalar::zero() }))
+    ).collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "sha256"), &inputs, &registry.exec_fn(), &function_options("sha256"))?;
+    assert_eq!(outputs.len(), 256);
+    for (out, exp) in outputs.iter().zip(&expected) {
+        let exp = if exp.get_value().unwrap() { Scalar::one() } else { Scalar::zero() };
+        assert_eq!(out.get_value(), Some(exp));
+    }
+    assert!(cs.is_satisfied());
+    // The same constraints, and a copy of the output bits that are not variables.
+    let copies = expected.iter().filter(|b| !matches!(b, Boolean::Is(_))).count();
+    assert_eq!(cs.num_constraints(), direct_cs.num_constraints() + copies);
+
+    // BLAKE2s with a personalization.
+    let mut options = function_options("blake2s");
+    options.configuration.as_mut().unwrap().push(KeyValue::from((PERSONALIZATION_KEY, "zkif1234")));
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "blake2s"), &inputs, &registry.exec_fn(), &options)?;
+    assert_eq!(outputs.len(), 256);
+    assert!(cs.is_satisfied());
+
+    // Inputs that are not bits.
+    let mut inputs = inputs;
+    inputs.truncate(8);
+    inputs[0] = AllocatedNum::alloc(cs.namespace(|| "two"), || Ok(Scalar::from(2)))?;
+    match call_gadget_with_options(&mut cs.namespace(|| "not bits"), &inputs, &registry.exec_fn(), &function_options("sha256")) {
+        Err(err) => assert!(err.to_string().contains("Input 0 is not a bit")),
+        Ok(_) => panic!("Should refuse inputs that are not bits"),
+    }
+
+    // Outputs must be new variables, also when only constraints are generated.
+    let call = CircuitHeader {
+        instance_variables: zkinterface::Variables { variable_ids: vec![1], values: None },
+        free_variable_id: 2,
+        ..CircuitHeader::default()
+    };
+    let respond = |target: Target, outputs: &[usize]| -> Result<()> {
+        let mut cs = GadgetCS::<Scalar>::from_call(BufferSink::default(), target, &call);
+        let input = alloc_input_nums(&mut cs, &[Some(Scalar::one())])?[0].get_variable();
+        let new = AllocatedNum::alloc(cs.namespace(|| "new"), || Ok(Scalar::one()))?.get_variable();
+        let vars = [input, new];
+        cs.finish_gadget(&outputs.iter().map(|i| vars[*i]).collect::<Vec<_>>())?;
+        Ok(())
+    };
+    for target in &[Target::Prover, Target::Preprocessing] {
+        assert!(respond(*target, &[1]).is_ok());
+        assert!(respond(*target, &[0]).unwrap_err().to_string().contains("not a new variable"));
+        assert!(respond(*target, &[1, 1]).unwrap_err().to_string().contains("not a new variable"));
+    }
+
+    Ok(())
+}
@@ -3,14 +3,16 @@ use bellman::{
     LinearCombination,
     SynthesisError,
     Variable,
+    gadgets::boolean::{AllocatedBit, Boolean},
     gadgets::num::AllocatedNum,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use zkinterface::{
-    CircuitHeader, Variables, Result,
-    consumers::reader::{Reader, Constraint, Term},
+    CircuitHeader, Command, Messages, Variables, KeyValue, Result,
+    consumers::reader::{Reader, Constraint, Term, Variable as ZkifVariable, collect_instance_variables},
 };
-use crate::export::write_scalar;
+use crate::export::{self, write_scalar};
 use ff::PrimeField;
 
 
@@ -24,14 +26,29 @@ pub fn read_scalar<Scalar: PrimeField>(
     {
         let repr: &mut [u8] = repr.as_mut();
         assert!(encoded.len() <= repr.len(), "Element is too big ({} > {} bytes)", encoded.len(), repr.len());
-        for i in 0..encoded.len() {
-            repr[i] = encoded[i];
-        }
+        repr[..encoded.len()].copy_from_slice(encoded);
     }
 
     Scalar::from_repr(repr).unwrap()
 }
 
+/// Like read_scalar, but return an error if the bytes are not a valid element.
+pub fn try_read_scalar<Scalar: PrimeField>(
+    encoded: &[u8],
+) -> Result<Scalar> {
+    let mut repr = Scalar::Repr::default();
+
+    {
+        let repr: &mut [u8] = repr.as_mut();
+        if encoded.len() > repr.len() {
+            return Err(format!("Element is too big ({} > {} bytes)", encoded.len(), repr.len()).into());
+        }
+        repr[..encoded.len()].copy_from_slice(encoded);
+    }
+
+    Scalar::from_repr(repr).ok_or_else(|| format!("Element is not in the field: {:?}", encoded).into())
+}
+
 /// Convert zkInterface terms to bellman LinearCombination.
 pub fn terms_to_lc<Scalar: PrimeField>(
     vars: &HashMap<u64, Variable>,
@@ -40,7 +57,7 @@ pub fn terms_to_lc<Scalar: PrimeField>(
     let mut lc = LinearCombination::zero();
     for term in terms {
         let coeff = read_scalar(term.value);
-        let var = vars.get(&term.id).unwrap().clone();
+        let var = *vars.get(&term.id).unwrap();
         lc = lc + (coeff, var);
     }
     lc
@@ -59,19 +76,258 @@ pub fn enforce<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
     );
 }
 
+/// How to number the input variables in a gadget call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputIds {
+    /// Number the inputs contiguously, starting from this id.
+    Contiguous(u64),
+    /// Use these ids, one per input, in order.
+    /// The gadget may allocate new variables from the highest id + 1.
+    Explicit(Vec<u64>),
+}
+
+impl Default for InputIds {
+    fn default() -> Self { InputIds::Contiguous(1) }
+}
+
+impl InputIds {
+    /// Assign ids to the inputs. Return the ids and the first free id.
+    pub fn assign(&self, num_inputs: usize) -> Result<(Vec<u64>, u64)> {
+        match self {
+            InputIds::Contiguous(first_id) => {
+                if *first_id == 0 {
+                    return Err("Input ids must start at 1 or higher (0 is the constant one).".into());
+                }
+                let free_variable_id = first_id + num_inputs as u64;
+                Ok(((*first_id..free_variable_id).collect(), free_variable_id))
+            }
+
+            InputIds::Explicit(ids) => {
+                if ids.len() != num_inputs {
+                    return Err(format!("Got {} input ids for {} inputs.", ids.len(), num_inputs).into());
+                }
+                let mut seen = HashSet::new();
+                for id in ids {
+                    if *id == 0 {
+                        return Err("Input id 0 is reserved for the constant one.".into());
+                    }
+                    if !seen.insert(*id) {
+                        return Err(format!("Input id {} is used more than once.", id).into());
+                    }
+                }
+                let free_variable_id = ids.iter().max().map_or(1, |max| max + 1);
+                Ok((ids.clone(), free_variable_id))
+            }
+        }
+    }
+}
+
+/// Options of call_gadget_with_options.
+#[derive(Clone, Debug, Default)]
+pub struct GadgetCallOptions {
+    pub input_ids: InputIds,
+    /// Parameters of the gadget, such as a hash function variant or a bit width.
+    pub configuration: Option<Vec<KeyValue>>,
+    /// What to ask the gadget, sent as a Command message after the call header.
+    /// By default, the constraints, and the witness if there are inputs and all of them have values.
+    /// Without constraints_generation, no constraints are enforced.
+    /// Without witness_generation, the new variables have no values, and evaluating them gives AssignmentMissing.
+    pub command: Option<Command>,
+}
+
+/// Check that a field_maximum from a zkInterface header describes the field of Scalar.
+pub fn check_field_maximum<Scalar: PrimeField>(field_maximum: &[u8]) -> Result<()> {
+    fn trim(bytes: &[u8]) -> &[u8] {
+        let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        &bytes[..len]
+    }
+
+    let supported = export::field_maximum::<Scalar>();
+    if trim(field_maximum) != trim(&supported) {
+        return Err(format!(
+            "The field is not supported (field_maximum {:?}, expected {:?}).",
+            field_maximum, supported).into());
+    }
+    Ok(())
+}
+
 /// Call a foreign gadget through zkInterface.
 pub fn call_gadget<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
     cs: &mut CS,
     inputs: &[AllocatedNum<Scalar>],
     exec_fn: &dyn Fn(&[u8]) -> Result<Reader>,
 ) -> Result<Vec<AllocatedNum<Scalar>>> {
-    let witness_generation = inputs.len() > 0 && inputs[0].get_value().is_some();
+    call_gadget_with_options(cs, inputs, exec_fn, &GadgetCallOptions::default())
+}
+
+/// Call a foreign gadget through zkInterface, with options.
+///
+/// The gadget must allocate its outputs and local variables from the free_variable_id of the call.
+/// They are mapped to new bellman variables, whatever their ids.
+pub fn call_gadget_with_options<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    inputs: &[AllocatedNum<Scalar>],
+    exec_fn: &dyn Fn(&[u8]) -> Result<Reader>,
+    options: &GadgetCallOptions,
+) -> Result<Vec<AllocatedNum<Scalar>>> {
+    call_gadget_inner(cs, &num_inputs(inputs), exec_fn, options, None, alloc_num_output)
+}
+
+/// Call a foreign gadget through zkInterface, reusing its constraints from previous calls.
+///
+/// `gadget` identifies the gadget behind exec_fn in the cache. The first call of a given shape
+/// (the gadget, the input ids, and the configuration) generates the constraints and stores them.
+/// Later calls of the same shape only ask the gadget for a witness, with a Command message after
+/// the call header, or do not call it at all when there are no input values.
+/// The stored constraints are then enforced on the new variables.
+pub fn call_gadget_cached<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    inputs: &[AllocatedNum<Scalar>],
+    exec_fn: &dyn Fn(&[u8]) -> Result<Reader>,
+    options: &GadgetCallOptions,
+    cache: &GadgetCache,
+    gadget: &str,
+) -> Result<Vec<AllocatedNum<Scalar>>> {
+    call_gadget_inner(cs, &num_inputs(inputs), exec_fn, options, Some((cache, gadget)), alloc_num_output)
+}
+
+/// Whether to trust a gadget to constrain its output bits to be 0 or 1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Booleanity {
+    /// The gadget constrains its outputs to be bits. Do not add constraints.
+    Trust,
+    /// Add a booleanity constraint per output.
+    Enforce,
+}
+
+/// Call a foreign gadget that works on bits.
+///
+/// Each bit is one zkInterface variable with the value 0 or 1.
+/// An input `Boolean::Is` is given to the gadget as its own variable. A negated or constant input
+/// is first copied into a new variable, with one constraint. Each output variable becomes an AllocatedBit,
+/// constrained to be a bit or not depending on `booleanity`.
+pub fn call_gadget_bits<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    inputs: &[Boolean],
+    exec_fn: &dyn Fn(&[u8]) -> Result<Reader>,
+    options: &GadgetCallOptions,
+    booleanity: Booleanity,
+) -> Result<Vec<Boolean>> {
+    let mut input_vars = vec![];
+    for (i, bit) in inputs.iter().enumerate() {
+        let value = bit.get_value().map(bit_to_scalar);
+        let var = match bit {
+            Boolean::Is(bit) => bit.get_variable(),
+            _ => {
+                let mut cs = cs.namespace(|| format!("input_{}", i));
+                let copy = AllocatedNum::alloc(cs.namespace(|| "copy"), || value.ok_or(SynthesisError::AssignmentMissing))?;
+                cs.enforce(|| "copy = bit",
+                           |lc| lc + copy.get_variable(),
+                           |lc| lc + CS::one(),
+                           |_| bit.lc(CS::one(), Scalar::one()));
+                copy.get_variable()
+            }
+        };
+        input_vars.push((var, value));
+    }
+
+    call_gadget_inner(cs, &input_vars, exec_fn, options, None, |cs, id, value| {
+        let bit = match value {
+            None => None,
+            Some(v) if v.is_zero() => Some(false),
+            Some(v) if v == Scalar::one() => Some(true),
+            Some(_) => return Err("The gadget returned an output that is not a bit.".into()),
+        };
+        let mut cs = cs.namespace(|| format!("output_{}", id));
+        let bit = match booleanity {
+            Booleanity::Enforce => AllocatedBit::alloc(cs, bit)?,
+            Booleanity::Trust => AllocatedBit::alloc(SkipEnforce(&mut cs), bit)?,
+        };
+        let var = bit.get_variable();
+        Ok((Boolean::from(bit), var))
+    })
+}
+
+fn bit_to_scalar<Scalar: PrimeField>(bit: bool) -> Scalar {
+    if bit { Scalar::one() } else { Scalar::zero() }
+}
+
+fn num_inputs<Scalar: PrimeField>(inputs: &[AllocatedNum<Scalar>]) -> Vec<(Variable, Option<Scalar>)> {
+    inputs.iter().map(|num| (num.get_variable(), num.get_value())).collect()
+}
+
+fn alloc_num_output<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    cs: &mut CS,
+    id: u64,
+    value: Option<Scalar>,
+) -> Result<(AllocatedNum<Scalar>, Variable)> {
+    let num = AllocatedNum::alloc(cs.namespace(|| format!("output_{}", id)), || value.ok_or(SynthesisError::AssignmentMissing))?;
+    let var = num.get_variable();
+    Ok((num, var))
+}
+
+/// Allocate variables in a constraint system, but drop constraints.
+struct SkipEnforce<'a, CS>(&'a mut CS);
+
+impl<'a, Scalar: PrimeField, CS: ConstraintSystem<Scalar>> ConstraintSystem<Scalar> for SkipEnforce<'a, CS> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, annotation: A, f: F) -> std::result::Result<Variable, SynthesisError>
+        where F: FnOnce() -> std::result::Result<Scalar, SynthesisError>, A: FnOnce() -> AR, AR: Into<String>
+    {
+        self.0.alloc(annotation, f)
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, annotation: A, f: F) -> std::result::Result<Variable, SynthesisError>
+        where F: FnOnce() -> std::result::Result<Scalar, SynthesisError>, A: FnOnce() -> AR, AR: Into<String>
+    {
+        self.0.alloc_input(annotation, f)
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, _a: LA, _b: LB, _c: LC)
+        where A: FnOnce() -> AR, AR: Into<String>,
+              LA: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+              LB: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>,
+              LC: FnOnce(LinearCombination<Scalar>) -> LinearCombination<Scalar>
+    {}
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N) where NR: Into<String>, N: FnOnce() -> NR {
+        self.0.get_root().push_namespace(name_fn)
+    }
+
+    fn pop_namespace(&mut self) {
+        self.0.get_root().pop_namespace()
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// Call a gadget with input variables and values, and allocate each output with alloc_output.
+fn call_gadget_inner<Scalar, CS, O, A>(
+    cs: &mut CS,
+    inputs: &[(Variable, Option<Scalar>)],
+    exec_fn: &dyn Fn(&[u8]) -> Result<Reader>,
+    options: &GadgetCallOptions,
+    cache: Option<(&GadgetCache, &str)>,
+    mut alloc_output: A,
+) -> Result<Vec<O>>
+    where Scalar: PrimeField,
+          CS: ConstraintSystem<Scalar>,
+          A: FnMut(&mut CS, u64, Option<Scalar>) -> Result<(O, Variable)>,
+{
+    let command = options.command.clone().unwrap_or(Command {
+        constraints_generation: true,
+        witness_generation: !inputs.is_empty() && inputs.iter().all(|(_, value)| value.is_some()),
+    });
+    let witness_generation = command.witness_generation;
 
     // Serialize input values.
     let values = if witness_generation {
         let mut values = Vec::<u8>::new();
-        for i in inputs {
-            let val = i.get_value().unwrap();
+        for (i, (_, value)) in inputs.iter().enumerate() {
+            let val = value.ok_or_else(|| format!("Witness generation requires the value of input {}.", i))?;
             write_scalar(&val, &mut values);
         }
         Some(values)
@@ -80,59 +336,112 @@ pub fn call_gadget<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
     };
 
     // Describe the input variables.
-    let first_input_id = 1;
-    let free_variable_id = first_input_id + inputs.len() as u64;
+    let (input_ids, free_variable_id) = options.input_ids.assign(inputs.len())?;
 
     let call_header = CircuitHeader {
         instance_variables: Variables {
-            variable_ids: (first_input_id..free_variable_id).collect(),
+            variable_ids: input_ids,
             values,
         },
         free_variable_id,
-        field_maximum: None,
-        configuration: None,
+        field_maximum: Some(export::field_maximum::<Scalar>()),
+        configuration: options.configuration.clone(),
     };
 
     // Prepare the call.
     let mut call_buf = vec![];
     call_header.write_into(&mut call_buf)?;
 
-    // Call.
-    let response = exec_fn(&call_buf).or(Err(SynthesisError::Unsatisfiable))?;
+    // Only complete constraint systems are cached.
+    let shape = cache
+        .filter(|_| command.constraints_generation)
+        .map(|(cache, gadget)| (cache, CallShape::new(gadget, &call_header)));
+    let cached = shape.as_ref().and_then(|(cache, shape)| cache.get(shape));
+
+    let no_constraints = Reader::new();
+    let response;
+    let new_vars;
+    let constraints;
+
+    match &cached {
+        None => {
+            // Call.
+            command.write_into(&mut call_buf)?;
+
+            response = exec_fn(&call_buf)
+                .map_err(|err| format!("Gadget call failed: {}", err))?;
+
+            new_vars = validate_response::<Scalar>(&call_header, &response)
+                .map_err(|err| format!("Invalid gadget response: {}", err))?;
+
+            if let Some((cache, shape)) = shape {
+                cache.insert(shape, CachedGadget::new(&new_vars, &response)?);
+            }
+            constraints = if command.constraints_generation { &response } else { &no_constraints };
+        }
+
+        Some(cached) if witness_generation => {
+            // Call for the witness only.
+            Command {
+                constraints_generation: false,
+                witness_generation: true,
+            }.write_into(&mut call_buf)?;
+
+            response = exec_fn(&call_buf)
+                .map_err(|err| format!("Gadget call failed: {}", err))?;
+
+            new_vars = validate_response::<Scalar>(&call_header, &response)
+                .map_err(|err| format!("Invalid gadget response: {}", err))?;
+
+            if !cached.has_layout(&new_vars) {
+                return Err("Invalid gadget response: the variables differ from the cached call.".into());
+            }
+            constraints = &cached.constraints;
+        }
+
+        Some(cached) => {
+            // Nothing to ask the gadget.
+            new_vars = cached.layout();
+            constraints = &cached.constraints;
+        }
+    }
+
+    // Values are only read in witness mode.
+    let value_of = |var: &ZkifVariable| -> Option<Scalar> {
+        if witness_generation && !var.value.is_empty() {
+            Some(read_scalar(var.value))
+        } else {
+            None
+        }
+    };
 
     // Track variables by id. Used to convert constraints.
     let mut id_to_var = HashMap::<u64, Variable>::new();
 
     id_to_var.insert(0, CS::one());
 
-    for i in 0..inputs.len() {
-        id_to_var.insert(call_header.instance_variables.variable_ids[i], inputs[i].get_variable());
+    for (id, (input, _)) in call_header.instance_variables.variable_ids.iter().zip(inputs) {
+        id_to_var.insert(*id, *input);
     }
 
     // Collect output variables and values to return.
     let mut outputs = Vec::new();
 
     // Allocate outputs, with optional values.
-    if let Some(output_vars) = response.instance_variables() {
-        for var in output_vars {
-            let num = AllocatedNum::alloc(
-                cs.namespace(|| format!("output_{}", var.id)), || {
-                    Ok(read_scalar(var.value))
-                })?;
+    for var in &new_vars.outputs {
+        let (output, variable) = alloc_output(cs, var.id, value_of(var))?;
 
-            // Track output variable.
-            id_to_var.insert(var.id, num.get_variable());
-            outputs.push(num);
-        }
+        // Track output variable.
+        id_to_var.insert(var.id, variable);
+        outputs.push(output);
     }
 
     // Allocate private variables, with optional values.
-    let private_vars = response.private_variables().unwrap();
-
-    for var in private_vars {
+    for var in &new_vars.locals {
+        let value = value_of(var);
         let num = AllocatedNum::alloc(
             cs.namespace(|| format!("local_{}", var.id)), || {
-                Ok(read_scalar(var.value))
+                value.ok_or(SynthesisError::AssignmentMissing)
             })?;
 
         // Track private variable.
@@ -140,9 +449,651 @@ pub fn call_gadget<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
     };
 
     // Add gadget constraints.
-    for (i, constraint) in response.iter_constraints().enumerate() {
+    for (i, constraint) in constraints.iter_constraints().enumerate() {
         enforce(&mut cs.namespace(|| format!("constraint_{}", i)), &id_to_var, &constraint);
     }
 
     Ok(outputs)
 }
+
+
+/// Constraints of gadgets, shared between calls of the same shape.
+/// See call_gadget_cached.
+#[derive(Default)]
+pub struct GadgetCache {
+    gadgets: Mutex<HashMap<CallShape, Arc<CachedGadget>>>,
+}
+
+impl GadgetCache {
+    pub fn new() -> Self { GadgetCache::default() }
+
+    /// The number of distinct calls stored.
+    pub fn len(&self) -> usize {
+        self.gadgets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn clear(&self) {
+        self.gadgets.lock().unwrap().clear();
+    }
+
+    fn get(&self, shape: &CallShape) -> Option<Arc<CachedGadget>> {
+        self.gadgets.lock().unwrap().get(shape).cloned()
+    }
+
+    fn insert(&self, shape: CallShape, gadget: CachedGadget) {
+        self.gadgets.lock().unwrap().insert(shape, Arc::new(gadget));
+    }
+}
+
+/// The fields of a KeyValue, which is not hashable.
+type KeyValueKey = (String, Option<String>, Option<Vec<u8>>, i64);
+
+/// What determines the constraints of a gadget call, besides the values.
+#[derive(Debug, Eq, PartialEq, Hash)]
+struct CallShape {
+    gadget: String,
+    input_ids: Vec<u64>,
+    free_variable_id: u64,
+    configuration: Option<Vec<KeyValueKey>>,
+}
+
+impl CallShape {
+    fn new(gadget: &str, call: &CircuitHeader) -> Self {
+        CallShape {
+            gadget: gadget.to_string(),
+            input_ids: call.instance_variables.variable_ids.clone(),
+            free_variable_id: call.free_variable_id,
+            configuration: call.configuration.as_ref().map(|kvs|
+                kvs.iter().map(|kv| (kv.key.clone(), kv.text.clone(), kv.data.clone(), kv.number)).collect()),
+        }
+    }
+}
+
+struct CachedGadget {
+    output_ids: Vec<u64>,
+    local_ids: Vec<u64>,
+    /// The constraint messages of the first response.
+    constraints: Reader,
+}
+
+impl CachedGadget {
+    fn new(new_vars: &GadgetResponse, response: &Reader) -> Result<Self> {
+        let mut buf = vec![];
+        for constraints in Messages::from(response).constraint_systems {
+            constraints.write_into(&mut buf)?;
+        }
+        let mut reader = Reader::new();
+        if !buf.is_empty() {
+            reader.push_message(buf)?;
+        }
+
+        Ok(CachedGadget {
+            output_ids: new_vars.outputs.iter().map(|var| var.id).collect(),
+            local_ids: new_vars.locals.iter().map(|var| var.id).collect(),
+            constraints: reader,
+        })
+    }
+
+    fn has_layout(&self, new_vars: &GadgetResponse) -> bool {
+        new_vars.outputs.iter().map(|var| var.id).eq(self.output_ids.iter().cloned()) &&
+            new_vars.locals.iter().map(|var| var.id).eq(self.local_ids.iter().cloned())
+    }
+
+    /// The new variables, without values.
+    fn layout(&self) -> GadgetResponse<'static> {
+        let no_value = |&id: &u64| ZkifVariable { id, value: &[] };
+        GadgetResponse {
+            outputs: self.output_ids.iter().map(no_value).collect(),
+            locals: self.local_ids.iter().map(no_value).collect(),
+        }
+    }
+}
+
+/// Parse a call, as received by a gadget. If the call does not include a Command message,
+/// the gadget is expected to generate the constraints, and the witness if the inputs have values.
+pub fn read_call(call_msg: &[u8]) -> Result<(CircuitHeader, Command)> {
+    let mut reader = Reader::new();
+    reader.push_message(call_msg.to_vec())?;
+
+    let header = CircuitHeader::from(reader.last_header().ok_or("No CircuitHeader in the call.")?);
+    let command = reader.into_iter()
+        .filter_map(|msg| msg.message_as_command())
+        .last()
+        .map(Command::from)
+        .unwrap_or(Command {
+            constraints_generation: true,
+            witness_generation: header.instance_variables.values.is_some(),
+        });
+    Ok((header, command))
+}
+
+
+/// The new variables of a gadget response, after validation.
+pub struct GadgetResponse<'a> {
+    /// The outputs, in the order of the response header.
+    pub outputs: Vec<ZkifVariable<'a>>,
+    /// The local variables, with values if a witness was given.
+    pub locals: Vec<ZkifVariable<'a>>,
+}
+
+/// Check that a gadget response is consistent with the call, and collect its new variables.
+///
+/// The gadget must allocate its outputs and local variables between the free_variable_id of the call
+/// and the free_variable_id of its response. It may repeat the inputs in its header, but not redefine them.
+/// Its constraints may only use the constant one (id 0), the inputs, and its own variables.
+/// It must declare the field of Scalar, and all values must be valid elements of that field.
+pub fn validate_response<'a, Scalar: PrimeField>(
+    call: &CircuitHeader,
+    response: &'a Reader,
+) -> Result<GadgetResponse<'a>> {
+    let header = response.last_header().ok_or("No CircuitHeader in the response.")?;
+
+    // Check that the gadget worked in the same field.
+    let field_maximum = header.field_maximum().ok_or("The gadget response does not declare its field_maximum.")?;
+    check_field_maximum::<Scalar>(field_maximum)?;
+
+    let first_id = call.free_variable_id;
+    let free_id = header.free_variable_id();
+    if free_id < first_id {
+        return Err(format!("The free_variable_id of the response ({}) is lower than the call ({}).", free_id, first_id).into());
+    }
+    let is_new = |id: u64| first_id <= id && id < free_id;
+
+    let inputs: HashMap<u64, &[u8]> = call.instance_variables.get_variables().iter()
+        .map(|var| (var.id, var.value)).collect();
+
+    // Outputs.
+    let mut outputs = Vec::new();
+    let mut output_ids = HashSet::new();
+    let instance_variables = header.instance_variables().ok_or("No instance_variables in the response.")?;
+
+    for var in collect_instance_variables(&instance_variables, 0).unwrap_or_default() {
+        if var.id == 0 { continue; } // The constant one.
+
+        if let Some(input_value) = inputs.get(&var.id) {
+            if var.has_value() && !input_value.is_empty() &&
+                try_read_scalar::<Scalar>(var.value)? != try_read_scalar::<Scalar>(input_value)? {
+                return Err(format!("The response redefines the value of input {}.", var.id).into());
+            }
+            continue; // The gadget repeats an input.
+        }
+
+        if !is_new(var.id) {
+            return Err(format!("Output {} is outside of the range of new variables [{}, {}).", var.id, first_id, free_id).into());
+        }
+        if !output_ids.insert(var.id) {
+            return Err(format!("Output {} is defined more than once.", var.id).into());
+        }
+        if var.has_value() {
+            try_read_scalar::<Scalar>(var.value)?;
+        }
+        outputs.push(var);
+    }
+
+    // Witness of the local variables.
+    let mut witness = HashMap::new();
+    for var in response.iter_witness() {
+        if !is_new(var.id) {
+            return Err(format!("The witness assigns variable {} outside of the range of new variables [{}, {}).", var.id, first_id, free_id).into());
+        }
+        try_read_scalar::<Scalar>(var.value)?;
+        witness.insert(var.id, var.value);
+    }
+
+    // Constraints.
+    let mut used_ids: HashSet<u64> = output_ids.iter().chain(witness.keys()).cloned().collect();
+    for (i, constraint) in response.iter_constraints().enumerate() {
+        for term in constraint.a.iter().chain(&constraint.b).chain(&constraint.c) {
+            if is_new(term.id) {
+                used_ids.insert(term.id);
+            } else if term.id != 0 && !inputs.contains_key(&term.id) {
+                return Err(format!("Constraint {} uses an unknown variable {}.", i, term.id).into());
+            }
+            try_read_scalar::<Scalar>(term.value)?;
+        }
+    }
+
+    // Every new variable is allocated, so the range must not be larger than the variables used by the response.
+    if free_id - first_id > used_ids.len() as u64 {
+        return Err(format!("The range of new variables [{}, {}) is larger than the {} variables used by the response.",
+                           first_id, free_id, used_ids.len()).into());
+    }
+
+    let locals = (first_id..free_id)
+        .filter(|id| !output_ids.contains(id))
+        .map(|id| ZkifVariable {
+            id,
+            value: witness.get(&id).cloned().unwrap_or(&[]),
+        }).collect();
+
+    Ok(GadgetResponse { outputs, locals })
+}
+
+/// A gadget for tests: compute the sum of the squares of the inputs.
+/// It numbers its variables in an unusual order, and repeats the inputs in its response.
+#[cfg(test)]
+pub(crate) fn example_gadget(call_msg: &[u8]) -> Result<Reader> {
+    use std::convert::TryFrom;
+    use bls12_381::Scalar;
+    use zkinterface::{ConstraintSystem as ZkifConstraints, Witness, BilinearConstraint};
+
+    let call = CircuitHeader::try_from(call_msg)?;
+    let input_ids = &call.instance_variables.variable_ids;
+    let n = input_ids.len() as u64;
+
+    // Locals (the squares) in reverse order, then the output.
+    let square_ids: Vec<u64> = (0..n).map(|i| call.free_variable_id + n - 1 - i).collect();
+    let output_id = call.free_variable_id + n;
+
+    let encode = |values: &[Scalar]| {
+        let mut encoded = vec![];
+        for v in values { write_scalar(v, &mut encoded); }
+        encoded
+    };
+    let one = encode(&[Scalar::one()]);
+    let lc = |ids: Vec<u64>| Variables {
+        values: Some(one.repeat(ids.len())),
+        variable_ids: ids,
+    };
+
+    let mut constraints = ZkifConstraints::default();
+    for i in 0..input_ids.len() {
+        constraints.constraints.push(BilinearConstraint {
+            linear_combination_a: lc(vec![input_ids[i]]),
+            linear_combination_b: lc(vec![input_ids[i]]),
+            linear_combination_c: lc(vec![square_ids[i]]),
+        });
+    }
+    constraints.constraints.push(BilinearConstraint {
+        linear_combination_a: lc(square_ids.clone()),
+        linear_combination_b: lc(vec![0]),
+        linear_combination_c: lc(vec![output_id]),
+    });
+
+    let input_values: Option<Vec<Scalar>> = call.instance_variables.values.as_ref().map(|_|
+        call.instance_variables.get_variables().iter().map(|var| read_scalar(var.value)).collect());
+
+    let mut response = CircuitHeader {
+        instance_variables: Variables {
+            variable_ids: input_ids.iter().cloned().chain(Some(output_id)).collect(),
+            values: None,
+        },
+        free_variable_id: output_id + 1,
+        field_maximum: call.field_maximum.clone(),
+        configuration: None,
+    };
+
+    let mut reader = Reader::new();
+    let mut buf = vec![];
+    constraints.write_into(&mut buf)?;
+
+    if let Some(input_values) = input_values {
+        let squares: Vec<Scalar> = input_values.iter().map(|x| x.square()).collect();
+        let output = squares.iter().fold(Scalar::zero(), |sum, x| sum + x);
+
+        let mut instance_values = input_values;
+        instance_values.push(output);
+        response.instance_variables.values = Some(encode(&instance_values));
+
+        Witness {
+            assigned_variables: Variables {
+                variable_ids: square_ids,
+                values: Some(encode(&squares)),
+            }
+        }.write_into(&mut buf)?;
+    }
+
+    response.write_into(&mut buf)?;
+    reader.push_message(buf)?;
+    Ok(reader)
+}
+
+#[test]
+fn test_call_gadget() -> Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = (0..3).map(|i|
+        AllocatedNum::alloc(cs.namespace(|| format!("input_{}", i)), || Ok(Scalar::from(i + 2)))
+    ).collect::<std::result::Result<Vec<_>, _>>()?;
+
+    // The default numbering.
+    let outputs = call_gadget(&mut cs.namespace(|| "default"), &inputs, &example_gadget)?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(4 + 9 + 16)));
+
+    // Explicit, non-contiguous input ids.
+    let options = GadgetCallOptions {
+        input_ids: InputIds::Explicit(vec![10, 4, 7]),
+        ..GadgetCallOptions::default()
+    };
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "explicit"), &inputs, &example_gadget, &options)?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(4 + 9 + 16)));
+
+    // Reserve the ids below 100.
+    let options = GadgetCallOptions {
+        input_ids: InputIds::Contiguous(100),
+        ..GadgetCallOptions::default()
+    };
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "reserved"), &inputs[1..], &example_gadget, &options)?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9 + 16)));
+
+    assert!(cs.is_satisfied());
+    // Inputs, then each call allocates squares and the output.
+    assert_eq!(cs.num_inputs(), 1);
+    assert_eq!(cs.num_constraints(), 4 + 4 + 3);
+
+    // Invalid assignments.
+    for (i, ids) in [vec![1, 2], vec![1, 2, 1], vec![0, 1, 2]].iter().enumerate() {
+        let options = GadgetCallOptions {
+            input_ids: InputIds::Explicit(ids.clone()),
+            ..GadgetCallOptions::default()
+        };
+        assert!(call_gadget_with_options(&mut cs.namespace(|| format!("invalid_{}", i)), &inputs, &example_gadget, &options).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_call_gadget_field_and_configuration() -> Result<()> {
+    use std::convert::TryFrom;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+
+    let options = GadgetCallOptions {
+        configuration: Some(vec![KeyValue::from(("variant", "squares"))]),
+        ..GadgetCallOptions::default()
+    };
+
+    // The gadget receives the field and the configuration.
+    let checking_gadget = |call_msg: &[u8]| {
+        let call = CircuitHeader::try_from(call_msg)?;
+        check_field_maximum::<Scalar>(call.field_maximum.as_ref().unwrap())?;
+        assert_eq!(call.configuration, options.configuration);
+        example_gadget(call_msg)
+    };
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "configured"), &inputs, &checking_gadget, &options)?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+
+    // A response in another field is refused.
+    let other_field_gadget = |call_msg: &[u8]| {
+        let mut call = CircuitHeader::try_from(call_msg)?;
+        call.field_maximum = Some(vec![100]);
+        let mut other_call_msg = vec![];
+        call.write_into(&mut other_call_msg)?;
+        example_gadget(&other_call_msg)
+    };
+    match call_gadget(&mut cs.namespace(|| "other field"), &inputs, &other_field_gadget) {
+        Err(err) => assert!(err.to_string().contains("field")),
+        Ok(_) => panic!("Should refuse another field"),
+    }
+
+    assert!(check_field_maximum::<Scalar>(&export::field_maximum::<Scalar>()).is_ok());
+    assert!(check_field_maximum::<Scalar>(&[100]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_validate_response() -> Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use zkinterface::{ConstraintSystem as ZkifConstraints, Witness};
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+
+    fn encode(values: &[u64]) -> Option<Vec<u8>> {
+        let mut encoded = vec![];
+        for v in values { write_scalar(&Scalar::from(*v), &mut encoded); }
+        Some(encoded)
+    }
+
+    // A valid response to x: output 2 = x * x, using a local 3 = x + 1 for no reason.
+    let valid_response = || {
+        let header = CircuitHeader {
+            instance_variables: Variables { variable_ids: vec![1, 2], values: encode(&[3, 9]) },
+            free_variable_id: 4,
+            field_maximum: Some(export::field_maximum::<Scalar>()),
+            configuration: None,
+        };
+        let constraints = ZkifConstraints::from(&[
+            ((vec![1], vec![1]), (vec![1], vec![1]), (vec![2], vec![1])),
+            ((vec![1, 0], vec![1, 1]), (vec![0], vec![1]), (vec![3], vec![1])),
+        ][..]);
+        let witness = Witness {
+            assigned_variables: Variables { variable_ids: vec![3], values: encode(&[4]) },
+        };
+        (header, constraints, witness)
+    };
+
+    let respond = |(header, constraints, witness): (CircuitHeader, ZkifConstraints, Witness)| {
+        let mut buf = vec![];
+        header.write_into(&mut buf)?;
+        constraints.write_into(&mut buf)?;
+        witness.write_into(&mut buf)?;
+        let mut reader = Reader::new();
+        reader.push_message(buf)?;
+        Ok(reader)
+    };
+
+    let outputs = call_gadget(&mut cs.namespace(|| "valid"), &inputs, &|_| respond(valid_response()))?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+    assert!(cs.is_satisfied());
+
+    type Mutation = fn(&mut (CircuitHeader, ZkifConstraints, Witness));
+    let invalid: Vec<(&str, Mutation)> = vec![
+        ("redefines the value of input 1", |r| r.0.instance_variables.values = encode(&[4, 9])),
+        ("Output 5 is outside", |r| r.0.instance_variables.variable_ids[1] = 5),
+        ("Output 2 is defined more than once", |r| {
+            r.0.instance_variables.variable_ids.push(2);
+            r.0.instance_variables.values = encode(&[3, 9, 9]);
+        }),
+        ("lower than the call", |r| r.0.free_variable_id = 1),
+        ("not supported", |r| r.0.field_maximum = Some(vec![7])),
+        ("does not declare its field_maximum", |r| r.0.field_maximum = None),
+        ("Constraint 1 uses an unknown variable 7", |r| r.1.constraints[1].linear_combination_c.variable_ids[0] = 7),
+        ("witness assigns variable 1", |r| r.2.assigned_variables.variable_ids[0] = 1),
+        ("not in the field", |r| r.2.assigned_variables.values = Some(vec![255; 32])),
+        // Would allocate a variable for each id.
+        ("larger than the 2 variables used", |r| r.0.free_variable_id = u64::MAX),
+    ];
+
+    for (i, (expected_error, mutate)) in invalid.into_iter().enumerate() {
+        let mut response = valid_response();
+        mutate(&mut response);
+        match call_gadget(&mut cs.namespace(|| format!("invalid_{}", i)), &inputs, &|_| respond(response.clone())) {
+            Ok(_) => panic!("Should refuse a response that {}", expected_error),
+            Err(err) => assert!(err.to_string().contains(expected_error), "{} does not contain {}", err, expected_error),
+        }
+    }
+
+    // No header at all.
+    let no_header = |_: &[u8]| Ok(Reader::new());
+    assert!(call_gadget(&mut cs.namespace(|| "no header"), &inputs, &no_header).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_call_gadget_cached() -> Result<()> {
+    use std::cell::Cell;
+    use bellman::SynthesisError;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::zkif_cs::{ZkifCS, Target};
+
+    let constraint_calls = Cell::new(0);
+    let witness_calls = Cell::new(0);
+
+    // The example gadget, honoring witness-only calls.
+    let gadget = |call_msg: &[u8]| {
+        let (_, command) = read_call(call_msg)?;
+        let response = example_gadget(call_msg)?;
+        if command.constraints_generation {
+            constraint_calls.set(constraint_calls.get() + 1);
+            return Ok(response);
+        }
+        witness_calls.set(witness_calls.get() + 1);
+
+        let messages = Messages::from(&response);
+        let mut buf = vec![];
+        messages.witnesses[0].write_into(&mut buf)?;
+        messages.circuit_headers[0].write_into(&mut buf)?;
+        let mut reader = Reader::new();
+        reader.push_message(buf)?;
+        Ok(reader)
+    };
+
+    let cache = GadgetCache::new();
+    let options = GadgetCallOptions::default();
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    for i in 0..5 {
+        let inputs = vec![
+            AllocatedNum::alloc(cs.namespace(|| format!("x_{}", i)), || Ok(Scalar::from(i)))?,
+            AllocatedNum::alloc(cs.namespace(|| format!("y_{}", i)), || Ok(Scalar::from(2 * i)))?,
+        ];
+        let outputs = call_gadget_cached(&mut cs.namespace(|| format!("call_{}", i)), &inputs, &gadget, &options, &cache, "squares")?;
+        assert_eq!(outputs[0].get_value(), Some(Scalar::from(5 * i * i)));
+    }
+    assert!(cs.is_satisfied());
+    assert_eq!(cs.num_constraints(), 5 * 3);
+    assert_eq!((constraint_calls.get(), witness_calls.get()), (1, 4));
+    assert_eq!(cache.len(), 1);
+
+    // Another configuration is another shape.
+    let configured = GadgetCallOptions {
+        configuration: Some(vec![KeyValue::from(("variant", "other"))]),
+        ..GadgetCallOptions::default()
+    };
+    let inputs = vec![
+        AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?,
+        AllocatedNum::alloc(cs.namespace(|| "y"), || Ok(Scalar::from(4)))?,
+    ];
+    call_gadget_cached(&mut cs.namespace(|| "configured"), &inputs, &gadget, &configured, &cache, "squares")?;
+    assert_eq!(cache.len(), 2);
+    assert!(cs.is_satisfied());
+
+    // Without values, a cached gadget is not called at all.
+    let mut cs = ZkifCS::<Scalar>::new("local/test_call_gadget_cached", Target::Preprocessing);
+    let inputs = vec![
+        AllocatedNum::alloc(cs.namespace(|| "x"), || Err(SynthesisError::AssignmentMissing))?,
+        AllocatedNum::alloc(cs.namespace(|| "y"), || Err(SynthesisError::AssignmentMissing))?,
+    ];
+    let outputs = call_gadget_cached(&mut cs, &inputs, &gadget, &options, &cache, "squares")?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(cs.num_constraints(), 3);
+    assert_eq!((constraint_calls.get(), witness_calls.get()), (2, 4));
+
+    Ok(())
+}
+
+#[test]
+fn test_call_gadget_bits() -> Result<()> {
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::gadget_server::{BellmanGadget, GadgetCS, alloc_input_bits, output_bits};
+    use crate::gadget_registry::ZkifGadget;
+
+    // XOR of all input bits, served by bellman.
+    let xor_gadget = BellmanGadget::new(|cs: &mut GadgetCS<Scalar>, values: &[Option<Scalar>], _: &CircuitHeader| {
+        let bits = alloc_input_bits(cs, values)?;
+        let mut acc = bits[0].clone();
+        for (i, bit) in bits[1..].iter().enumerate() {
+            acc = Boolean::xor(cs.namespace(|| format!("xor_{}", i)), &acc, bit)?;
+        }
+        output_bits(cs, &[acc])
+    });
+    let exec_fn = |call_msg: &[u8]| xor_gadget.call(call_msg);
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = Boolean::from(AllocatedBit::alloc(cs.namespace(|| "a"), Some(true))?);
+    let b = Boolean::from(AllocatedBit::alloc(cs.namespace(|| "b"), Some(true))?);
+    // Variables, a negation, and a constant.
+    let inputs = vec![a, b.not(), Boolean::constant(true)];
+    let options = GadgetCallOptions::default();
+
+    let before = cs.num_constraints();
+    let trusted = call_gadget_bits(&mut cs.namespace(|| "trust"), &inputs, &exec_fn, &options, Booleanity::Trust)?;
+    let trusted_constraints = cs.num_constraints() - before;
+    assert_eq!(trusted.len(), 1);
+    assert_eq!(trusted[0].get_value(), Some(false));
+
+    let before = cs.num_constraints();
+    let enforced = call_gadget_bits(&mut cs.namespace(|| "enforce"), &inputs, &exec_fn, &options, Booleanity::Enforce)?;
+    assert_eq!(enforced[0].get_value(), Some(false));
+    assert_eq!(cs.num_constraints() - before, trusted_constraints + 1);
+    assert!(cs.is_satisfied());
+
+    // The output of the example gadget is not a bit (1 + 0 + 1 = 2).
+    match call_gadget_bits(&mut cs.namespace(|| "not a bit"), &inputs, &example_gadget, &options, Booleanity::Enforce) {
+        Err(err) => assert!(err.to_string().contains("not a bit")),
+        Ok(_) => panic!("Should refuse an output that is not a bit"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_call_gadget_command() -> Result<()> {
+    use std::cell::RefCell;
+    use bellman::gadgets::test::TestConstraintSystem;
+    use bls12_381::Scalar;
+    use crate::zkif_cs::{ZkifCS, Target};
+
+    let commands = RefCell::new(vec![]);
+    let gadget = |call_msg: &[u8]| {
+        commands.borrow_mut().push(read_call(call_msg)?.1);
+        example_gadget(call_msg)
+    };
+    let constraints_only = Command { constraints_generation: true, witness_generation: false };
+
+    // Setup mode, without values.
+    let mut cs = ZkifCS::<Scalar>::new("local/test_call_gadget_command", Target::Preprocessing);
+    let inputs = vec![
+        AllocatedNum::alloc(cs.namespace(|| "x"), || Err(SynthesisError::AssignmentMissing))?,
+        AllocatedNum::alloc(cs.namespace(|| "y"), || Err(SynthesisError::AssignmentMissing))?,
+    ];
+    let outputs = call_gadget(&mut cs, &inputs, &gadget)?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(cs.num_constraints(), 3);
+
+    // No inputs and no private variables.
+    let options = GadgetCallOptions { command: Some(constraints_only.clone()), ..GadgetCallOptions::default() };
+    let outputs = call_gadget_with_options(&mut cs, &[], &gadget, &options)?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(*commands.borrow(), vec![constraints_only.clone(), constraints_only.clone()]);
+
+    // Without inputs, the default is not to ask for the witness.
+    call_gadget(&mut cs, &[], &gadget)?;
+    assert_eq!(commands.borrow()[2], constraints_only);
+
+    // Constraints only, even with values: the new variables have no values.
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+    assert!(call_gadget_with_options(&mut cs.namespace(|| "constraints only"), &inputs, &gadget, &options).is_err());
+
+    // The witness requires values.
+    let options = GadgetCallOptions {
+        command: Some(Command { constraints_generation: true, witness_generation: true }),
+        ..GadgetCallOptions::default()
+    };
+    let outputs = call_gadget_with_options(&mut cs.namespace(|| "witness"), &inputs, &gadget, &options)?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+
+    let mut setup_cs = ZkifCS::<Scalar>::new("local/test_call_gadget_command", Target::Preprocessing);
+    let no_value = vec![AllocatedNum::alloc(setup_cs.namespace(|| "x"), || Err(SynthesisError::AssignmentMissing))?];
+    match call_gadget_with_options(&mut setup_cs, &no_value, &gadget, &options) {
+        Err(err) => assert!(err.to_string().contains("requires the value of input 0")),
+        Ok(_) => panic!("Should require input values"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,468 @@
+//! Read zkInterface messages from their JSON form, as written by `flatc --json`.
+//!
+//! Keys may be quoted or not, and trailing commas are accepted. Each message is an object with
+//! `message_type` and `message`. Several messages are given one after the other, or in an array.
+//! The names of the earlier schema are accepted: `Circuit` with `connections`, and `R1CSConstraints`.
+//!
+//! Values are arrays of bytes as in the binary format, or arrays of strings with one field element each,
+//! in decimal or in hex with a 0x prefix. For instance `values: ["25", "0x1a"]`.
+
+use num_bigint::BigUint;
+use zkinterface::{BilinearConstraint, CircuitHeader, Command, ConstraintSystem, KeyValue, Reader, Result, Variables, Witness};
+
+/// A parsed JSON value. Numbers are kept as text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse a sequence of JSON values.
+    pub fn parse_all(text: &str) -> Result<Vec<Json>> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let mut values = vec![];
+        parser.skip_space();
+        while parser.pos < parser.text.len() {
+            values.push(parser.value()?);
+            parser.skip_space();
+            // Values may be separated by a comma.
+            if parser.peek() == Some(b',') {
+                parser.pos += 1;
+                parser.skip_space();
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self, name: &str) -> Result<&[Json]> {
+        match self {
+            Json::Array(items) => Ok(items),
+            _ => Err(format!("{} must be an array.", name).into()),
+        }
+    }
+
+    fn as_str(&self, name: &str) -> Result<&str> {
+        match self {
+            Json::String(s) => Ok(s),
+            _ => Err(format!("{} must be a string.", name).into()),
+        }
+    }
+
+    fn as_u64(&self, name: &str) -> Result<u64> {
+        match self {
+            Json::Number(n) => n.parse().map_err(|_| format!("{} must be a positive integer, got {}.", name, n).into()),
+            _ => Err(format!("{} must be a number.", name).into()),
+        }
+    }
+
+    fn as_bool(&self, name: &str) -> Result<bool> {
+        match self {
+            Json::Bool(b) => Ok(*b),
+            _ => Err(format!("{} must be true or false.", name).into()),
+        }
+    }
+}
+
+/// Whether a buffer looks like JSON rather than binary messages.
+pub fn is_json(buf: &[u8]) -> bool {
+    let first = buf.iter().find(|b| !b.is_ascii_whitespace());
+    matches!(first, Some(b'{') | Some(b'[')) && !buf.contains(&0)
+}
+
+/// Push messages into a Reader, from either the binary or the JSON form.
+pub fn push_messages(reader: &mut Reader, buf: Vec<u8>) -> Result<()> {
+    if is_json(&buf) {
+        let text = String::from_utf8(buf).map_err(|_| "The JSON messages are not valid UTF-8.")?;
+        // One buffer per message, to keep each aligned.
+        for msg in json_messages(&text)? {
+            reader.push_message(msg)?;
+        }
+        Ok(())
+    } else {
+        reader.push_message(buf)
+    }
+}
+
+/// Convert messages from JSON into the binary form.
+pub fn json_to_messages(text: &str) -> Result<Vec<u8>> {
+    Ok(json_messages(text)?.concat())
+}
+
+fn json_messages(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut out = vec![];
+    for value in Json::parse_all(text)? {
+        match &value {
+            Json::Array(messages) => for msg in messages { write_message(msg, &mut out)?; },
+            msg => write_message(msg, &mut out)?,
+        }
+    }
+    Ok(out)
+}
+
+fn write_message(json: &Json, out: &mut Vec<Vec<u8>>) -> Result<()> {
+    let mut buf = vec![];
+    let message_type = json.get("message_type").ok_or("A message has no message_type.")?.as_str("message_type")?;
+    let msg = json.get("message").ok_or("A message has no message.")?;
+
+    match message_type {
+        "CircuitHeader" | "Circuit" => {
+            let instance_variables = msg.get("instance_variables").or_else(|| msg.get("connections"));
+            CircuitHeader {
+                instance_variables: instance_variables.map(variables).transpose()?.unwrap_or_default(),
+                free_variable_id: msg.get("free_variable_id").map_or(Ok(0), |v| v.as_u64("free_variable_id"))?,
+                field_maximum: msg.get("field_maximum").map(field_element).transpose()?,
+                configuration: msg.get("configuration").map(configuration).transpose()?,
+            }.write_into(&mut buf)?;
+
+            // The earlier schema gave the command in the circuit.
+            let r1cs_generation = msg.get("r1cs_generation").map(|v| v.as_bool("r1cs_generation")).transpose()?;
+            let witness_generation = msg.get("witness_generation").map(|v| v.as_bool("witness_generation")).transpose()?;
+            if message_type == "Circuit" && (r1cs_generation.is_some() || witness_generation.is_some()) {
+                let mut command = vec![];
+                Command {
+                    constraints_generation: r1cs_generation.unwrap_or(false),
+                    witness_generation: witness_generation.unwrap_or(false),
+                }.write_into(&mut command)?;
+                out.push(buf);
+                buf = command;
+            }
+        }
+        "ConstraintSystem" | "R1CSConstraints" => {
+            let constraints = msg.get("constraints").map_or(Ok(&[][..]), |c| c.as_array("constraints"))?;
+            let lc = |constraint: &Json, name: &str| -> Result<Variables> {
+                constraint.get(name).map(variables).transpose().map(Option::unwrap_or_default)
+            };
+            ConstraintSystem {
+                constraints: constraints.iter().map(|constraint| Ok(BilinearConstraint {
+                    linear_combination_a: lc(constraint, "linear_combination_a")?,
+                    linear_combination_b: lc(constraint, "linear_combination_b")?,
+                    linear_combination_c: lc(constraint, "linear_combination_c")?,
+                })).collect::<Result<_>>()?,
+            }.write_into(&mut buf)?;
+        }
+        "Witness" => {
+            Witness {
+                assigned_variables: msg.get("assigned_variables").map(variables).transpose()?.unwrap_or_default(),
+            }.write_into(&mut buf)?;
+        }
+        "Command" => {
+            let flag = |name: &str| msg.get(name).map_or(Ok(false), |v| v.as_bool(name));
+            Command {
+                constraints_generation: flag("constraints_generation")?,
+                witness_generation: flag("witness_generation")?,
+            }.write_into(&mut buf)?;
+        }
+        _ => return Err(format!("Unknown message_type {}", message_type).into()),
+    }
+    out.push(buf);
+    Ok(())
+}
+
+fn variables(json: &Json) -> Result<Variables> {
+    let variable_ids = json.get("variable_ids").map_or(Ok(&[][..]), |ids| ids.as_array("variable_ids"))?
+        .iter().map(|id| id.as_u64("variable_ids")).collect::<Result<Vec<_>>>()?;
+    let values = json.get("values").map(|values| field_elements(values.as_array("values")?)).transpose()?;
+    Ok(Variables { variable_ids, values })
+}
+
+/// Bytes, or one field element per string, all encoded with the same size.
+fn field_elements(items: &[Json]) -> Result<Vec<u8>> {
+    if !items.iter().all(|item| matches!(item, Json::String(_))) {
+        return bytes(items);
+    }
+    let elements = items.iter()
+        .map(|item| parse_element(item.as_str("values")?))
+        .collect::<Result<Vec<_>>>()?;
+    let size = elements.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let mut buf = vec![];
+    for mut element in elements {
+        element.resize(size, 0);
+        buf.extend(element);
+    }
+    Ok(buf)
+}
+
+fn field_element(json: &Json) -> Result<Vec<u8>> {
+    match json {
+        Json::String(s) => parse_element(s),
+        _ => bytes(json.as_array("field_maximum")?),
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hex number, into little-endian bytes.
+fn parse_element(text: &str) -> Result<Vec<u8>> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => BigUint::parse_bytes(hex.as_bytes(), 16),
+        None => BigUint::parse_bytes(text.as_bytes(), 10),
+    };
+    Ok(parsed.ok_or_else(|| format!("Invalid field element {:?}.", text))?.to_bytes_le())
+}
+
+fn bytes(items: &[Json]) -> Result<Vec<u8>> {
+    items.iter().map(|item| {
+        let byte = item.as_u64("bytes")?;
+        if byte > 255 {
+            return Err(format!("{} is not a byte. Give field elements as strings.", byte).into());
+        }
+        Ok(byte as u8)
+    }).collect()
+}
+
+fn configuration(json: &Json) -> Result<Vec<KeyValue>> {
+    json.as_array("configuration")?.iter().map(|kv| Ok(KeyValue {
+        key: kv.get("key").ok_or("A KeyValue has no key.")?.as_str("key")?.to_string(),
+        text: kv.get("text").map(|t| t.as_str("text").map(str::to_string)).transpose()?,
+        data: kv.get("data").map(|d| bytes(d.as_array("data")?)).transpose()?,
+        number: match kv.get("number") {
+            Some(Json::Number(n)) => n.parse().map_err(|_| format!("Invalid number {}.", n))?,
+            Some(_) => return Err("number must be a number.".into()),
+            None => 0,
+        },
+    })).collect()
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, what: &str) -> Box<dyn std::error::Error> {
+        let line = 1 + self.text[..self.pos].iter().filter(|b| **b == b'\n').count();
+        format!("Invalid JSON at line {}: {}", line, what).into()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume the closing character and return true, or else a single comma, or a trailing comma with the closing character.
+    fn end_or_comma(&mut self, close: u8) -> Result<bool> {
+        self.skip_space();
+        if self.peek() == Some(close) {
+            self.pos += 1;
+            return Ok(true);
+        }
+        if self.peek() != Some(b',') {
+            return Err(self.error(&format!("expected ',' or '{}'", close as char)));
+        }
+        self.pos += 1;
+        self.skip_space();
+        if self.peek() == Some(close) {
+            self.pos += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip_space();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                self.skip_space();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let key = if self.peek() == Some(b'"') { self.string()? } else { self.word() };
+                    if key.is_empty() {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    if self.end_or_comma(b'}')? {
+                        return Ok(Json::Object(fields));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.skip_space();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.end_or_comma(b']')? {
+                        return Ok(Json::Array(items));
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(_) => match self.word().as_str() {
+                "" => Err(self.error("expected a value")),
+                "null" => Ok(Json::Null),
+                "true" => Ok(Json::Bool(true)),
+                "false" => Ok(Json::Bool(false)),
+                word if word.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => Ok(Json::Number(word.to_string())),
+                word => Err(self.error(&format!("unexpected {}", word))),
+            },
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    /// An unquoted key, number, or literal.
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'+' || c == b'.') {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.pos]).into_owned()
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'"' | b'\\' | b'/' => bytes.push(escaped),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // A character outside of the basic plane is a surrogate pair.
+                            if (0xD800..0xDC00).contains(&code) && self.text.get(self.pos..self.pos + 2) == Some(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            let c = std::char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?;
+                            bytes.extend(c.to_string().bytes());
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                _ => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    /// The four hex digits of a \u escape.
+    fn hex4(&mut self) -> Result<u32> {
+        let hex = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("invalid escape"))?;
+        if !hex.iter().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("invalid escape"));
+        }
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(hex)?, 16)?)
+    }
+}
+
+
+#[test]
+fn test_json_messages() -> Result<()> {
+    use std::fs::read_to_string;
+    use std::path::Path;
+    use bls12_381::Scalar;
+    use zkinterface::Messages;
+    use crate::zkif_backend::validate;
+    use crate::zokrates::read_legacy_file;
+
+    // The JSON twins of the ZoKrates messages, in the earlier schema.
+    let dir = Path::new("src/zokrates/messages");
+    let mut from_json = Reader::new();
+    let mut from_binary = Reader::new();
+    for name in &["r1cs", "circuit_r1cs", "witness", "circuit_witness"] {
+        push_messages(&mut from_json, read_to_string(dir.join(format!("{}.json", name)))?.into_bytes())?;
+        read_legacy_file(&mut from_binary, dir.join(format!("{}.zkif", name)))?;
+    }
+    let (json, binary) = (Messages::from(&from_json), Messages::from(&from_binary));
+    assert_eq!(json.circuit_headers, binary.circuit_headers);
+    assert_eq!(json.constraint_systems, binary.constraint_systems);
+    assert_eq!(json.witnesses, binary.witnesses);
+    validate::<Scalar>(&from_json, false)?;
+
+    // A hand-written circuit with field elements as strings: x * x = 0x19.
+    let text = r#"[
+      { message_type: "CircuitHeader", message: {
+          instance_variables: { variable_ids: [1], values: ["0x19"] },
+          free_variable_id: 3,
+          field_maximum: "52435875175126190479447740508185965837690552500527637822603658699938581184512",
+          configuration: [{ key: "name", text: "square" }],
+      }},
+      { "message_type": "ConstraintSystem", "message": { "constraints": [{
+          "linear_combination_a": { "variable_ids": [2], "values": ["1"] },
+          "linear_combination_b": { "variable_ids": [2], "values": ["1"] },
+          "linear_combination_c": { "variable_ids": [1], "values": ["1"] }
+      }]}},
+      { message_type: "Witness", message: { assigned_variables: { variable_ids: [2], values: ["5"] } } },
+    ]"#;
+    let mut reader = Reader::new();
+    push_messages(&mut reader, text.as_bytes().to_vec())?;
+    validate::<Scalar>(&reader, false)?;
+    let messages = Messages::from(&reader);
+    assert_eq!(messages.circuit_headers[0].instance_variables.values, Some(vec![25]));
+
+    let mut wrong = Reader::new();
+    push_messages(&mut wrong, text.replace("\"5\"", "\"6\"").into_bytes())?;
+    assert!(validate::<Scalar>(&wrong, false).is_err());
+
+    // The legacy call gives a Command.
+    let call = json_to_messages(&read_to_string(dir.join("call.json"))?)?;
+    let (header, command) = crate::import::read_call(&call)?;
+    assert_eq!(header.instance_variables.variable_ids, vec![1, 2]);
+    assert!(command.constraints_generation && command.witness_generation);
+
+    assert!(json_to_messages("{ message_type: \"Proof\", message: {} }").is_err());
+    assert!(json_to_messages("{ message_type: \"Witness\", message: { ").is_err());
+    assert!(!is_json(&call));
+
+    // Standard escapes, and a single separator comma between elements.
+    let parsed = Json::parse_all(r#"["a\"\\\/\b\f\n\r\t", "\u00e9\uD83D\uDE00"] {a:1, b:[2,],}"#)?;
+    assert_eq!(parsed[0], Json::Array(vec![
+        Json::String("a\"\\/\u{8}\u{c}\n\r\t".to_string()),
+        Json::String("é😀".to_string()),
+    ]));
+    assert_eq!(parsed[1].get("b"), Some(&Json::Array(vec![Json::Number("2".to_string())])));
+    for invalid in &["[1,,,2]", "{a:1,,b:2}", "[,1]", "{,}", "[1 2]", "\"\\x\"", "\"\\uD83D\\u0041\""] {
+        assert!(Json::parse_all(invalid).is_err(), "{}", invalid);
+    }
+    Ok(())
+}
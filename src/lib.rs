@@ -2,6 +2,19 @@ pub mod import;
 pub mod export;
 pub mod zkif_backend;
 pub mod zkif_cs;
+pub mod compression;
+pub mod process_gadget;
+pub mod recorded_gadget;
+pub mod gadget_registry;
+pub mod gadget_server;
+pub mod gadget_circuits;
+pub mod zokrates;
+pub mod circom;
+pub mod json;
+pub mod codegen;
+pub mod parameters;
+pub mod compressed_inputs;
+pub mod batch;
 
 // Reexport dependencies for convenience.
 pub use zkinterface;
@@ -10,5 +23,3 @@ pub use ff;
 pub use pairing;
 pub use bls12_381;
 
-#[cfg(feature = "zokrates")]
-pub mod demo_import_from_zokrates;
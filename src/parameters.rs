@@ -0,0 +1,261 @@
+//! Load Groth16 parameters, with point checks for untrusted keys, or memory-mapped for large keys.
+//!
+//! The file format is the one of `Parameters::write`: the verifying key, then the vectors
+//! h, l, a, b_g1, b_g2, each with a big-endian u32 length and uncompressed points.
+
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use bellman::SynthesisError;
+use bellman::groth16::{Parameters, ParameterSource, VerifyingKey};
+use bellman::multiexp::{Source, SourceBuilder};
+use group::UncompressedEncoding;
+use group::prime::PrimeCurveAffine;
+use memmap::Mmap;
+use pairing::Engine;
+use zkinterface::Result;
+
+/// How to load a proving key, and which circuit it is for.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct KeyOptions {
+    /// Check that all points are on the curve and in the prime-order subgroup.
+    /// Needed for keys from untrusted sources; slow for large keys.
+    pub checked: bool,
+    /// Map the file into memory and decode points when they are used, instead of reading it all.
+    pub mmap: bool,
+    /// Use the circuit with its instance variables compressed into one public input (see `compressed_inputs`).
+    /// The key must have been generated with the same option.
+    pub compress_inputs: bool,
+}
+
+/// Read parameters entirely into memory.
+pub fn read_parameters<E: Engine>(path: &Path, checked: bool) -> Result<Parameters<E>> {
+    let file = File::open(path).map_err(|err| format!("Cannot open {}: {}", path.display(), err))?;
+    Parameters::read(io::BufReader::new(file), checked)
+        .map_err(|err| format!("Invalid parameters in {}: {}", path.display(), err).into())
+}
+
+/// A vector of points in the file.
+#[derive(Copy, Clone, Debug)]
+struct Section {
+    offset: usize,
+    len: usize,
+}
+
+/// Parameters in a memory-mapped file, used as a ParameterSource to create proofs.
+///
+/// Only the verifying key is decoded when opening. The other points are decoded while proving,
+/// so the file is paged in by the OS as needed and stays cached between proofs.
+pub struct MmapParameters<E: Engine> {
+    pub vk: VerifyingKey<E>,
+    map: Arc<Mmap>,
+    h: Section,
+    l: Section,
+    a: Section,
+    b_g1: Section,
+    b_g2: Section,
+}
+
+impl<E: Engine> MmapParameters<E> {
+    /// Map a parameters file. If checked, all points are validated once here.
+    pub fn open(path: &Path, checked: bool) -> Result<Self> {
+        let file = File::open(path).map_err(|err| format!("Cannot open {}: {}", path.display(), err))?;
+        // The file must not be modified while mapped.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let mut vk_bytes = &map[..];
+        let vk = VerifyingKey::<E>::read(&mut vk_bytes)
+            .map_err(|err| format!("Invalid verifying key in {}: {}", path.display(), err))?;
+        let mut offset = map.len() - vk_bytes.len();
+
+        let g1_size = point_size::<E::G1Affine>();
+        let g2_size = point_size::<E::G2Affine>();
+        let mut section = |point_size: usize| -> Result<Section> {
+            let len_bytes = map.get(offset..offset + 4).ok_or("Truncated parameters file.")?;
+            let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+            let section = Section { offset: offset + 4, len };
+            offset = section.offset + len * point_size;
+            if offset > map.len() {
+                return Err("Truncated parameters file.".into());
+            }
+            Ok(section)
+        };
+        let (h, l, a, b_g1) = (section(g1_size)?, section(g1_size)?, section(g1_size)?, section(g1_size)?);
+        let b_g2 = section(g2_size)?;
+        if offset != map.len() {
+            return Err(format!("{} unexpected bytes at the end of {}.", map.len() - offset, path.display()).into());
+        }
+
+        let params = MmapParameters { vk, map: Arc::new(map), h, l, a, b_g1, b_g2 };
+        if checked {
+            params.check()?;
+        }
+        Ok(params)
+    }
+
+    /// Check that all points are valid and not the identity, as Parameters::read with checks.
+    pub fn check(&self) -> Result<()> {
+        for (name, section) in &[("h", self.h), ("l", self.l), ("a", self.a), ("b_g1", self.b_g1)] {
+            let bases = self.bases::<E::G1Affine>(*section, 0);
+            for i in 0..section.len {
+                bases.decode(i, true).map_err(|err| format!("Invalid point {} of {}: {}", i, name, err))?;
+            }
+        }
+        let bases = self.bases::<E::G2Affine>(self.b_g2, 0);
+        for i in 0..self.b_g2.len {
+            bases.decode(i, true).map_err(|err| format!("Invalid point {} of b_g2: {}", i, err))?;
+        }
+        Ok(())
+    }
+
+    fn bases<G: UncompressedEncoding>(&self, section: Section, start: usize) -> MmapBases<G> {
+        MmapBases { map: self.map.clone(), section, start, phantom: PhantomData }
+    }
+}
+
+impl<E: Engine> ParameterSource<E> for &MmapParameters<E> {
+    type G1Builder = MmapBases<E::G1Affine>;
+    type G2Builder = MmapBases<E::G2Affine>;
+
+    fn get_vk(&mut self, _: usize) -> std::result::Result<VerifyingKey<E>, SynthesisError> {
+        Ok(self.vk.clone())
+    }
+
+    fn get_h(&mut self, _: usize) -> std::result::Result<Self::G1Builder, SynthesisError> {
+        Ok(self.bases(self.h, 0))
+    }
+
+    fn get_l(&mut self, _: usize) -> std::result::Result<Self::G1Builder, SynthesisError> {
+        Ok(self.bases(self.l, 0))
+    }
+
+    fn get_a(&mut self, num_inputs: usize, _: usize) -> std::result::Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok((self.bases(self.a, 0), self.bases(self.a, num_inputs)))
+    }
+
+    fn get_b_g1(&mut self, num_inputs: usize, _: usize) -> std::result::Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok((self.bases(self.b_g1, 0), self.bases(self.b_g1, num_inputs)))
+    }
+
+    fn get_b_g2(&mut self, num_inputs: usize, _: usize) -> std::result::Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
+        Ok((self.bases(self.b_g2, 0), self.bases(self.b_g2, num_inputs)))
+    }
+}
+
+fn point_size<G: UncompressedEncoding>() -> usize {
+    G::Uncompressed::default().as_ref().len()
+}
+
+/// The points of a section of the mapped file, from a start index.
+pub struct MmapBases<G> {
+    map: Arc<Mmap>,
+    section: Section,
+    start: usize,
+    phantom: PhantomData<G>,
+}
+
+impl<G> Clone for MmapBases<G> {
+    fn clone(&self) -> Self {
+        MmapBases { map: self.map.clone(), section: self.section, start: self.start, phantom: PhantomData }
+    }
+}
+
+impl<G: PrimeCurveAffine + UncompressedEncoding> MmapBases<G> {
+    fn decode(&self, i: usize, checked: bool) -> std::result::Result<G, SynthesisError> {
+        let size = point_size::<G>();
+        let offset = self.section.offset + i * size;
+        let mut repr = G::Uncompressed::default();
+        repr.as_mut().copy_from_slice(&self.map[offset..offset + size]);
+
+        let point = if checked { G::from_uncompressed(&repr) } else { G::from_uncompressed_unchecked(&repr) };
+        let point: G = Option::from(point)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid point"))?;
+        if point.is_identity().into() {
+            return Err(SynthesisError::UnexpectedIdentity);
+        }
+        Ok(point)
+    }
+}
+
+impl<G: PrimeCurveAffine + UncompressedEncoding> SourceBuilder<G> for MmapBases<G> {
+    type Source = MmapSource<G>;
+
+    fn new(self) -> MmapSource<G> {
+        MmapSource { position: self.start, bases: self, current: G::identity() }
+    }
+}
+
+/// Decode the points of a section one at a time.
+pub struct MmapSource<G> {
+    bases: MmapBases<G>,
+    position: usize,
+    current: G,
+}
+
+impl<G: PrimeCurveAffine + UncompressedEncoding> Source<G> for MmapSource<G> {
+    fn next(&mut self) -> std::result::Result<&G, SynthesisError> {
+        if self.position >= self.bases.section.len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "expected more bases from source").into());
+        }
+        self.current = self.bases.decode(self.position, false)?;
+        self.position += 1;
+        Ok(&self.current)
+    }
+
+    fn skip(&mut self, amt: usize) -> std::result::Result<(), SynthesisError> {
+        if self.position >= self.bases.section.len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "expected more bases from source").into());
+        }
+        self.position += amt;
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_mmap_parameters() -> Result<()> {
+    use std::fs::{create_dir_all, write};
+    use std::io::Write;
+    use bellman::groth16::{create_random_proof, generate_random_parameters, prepare_verifying_key, verify_proof};
+    use bls12_381::{Bls12, Scalar};
+    use crate::gadget_circuits::BuiltinGadget;
+
+    let dir = Path::new("local/test_mmap_parameters");
+    create_dir_all(dir)?;
+    let path = dir.join("bellman-pk");
+
+    let circuit = BuiltinGadget::parse("multipack", &["0102"])?;
+    let mut rng = rand::thread_rng();
+    let params = generate_random_parameters::<Bls12, _, _>(circuit.clone(), &mut rng)?;
+    params.write(File::create(&path)?)?;
+
+    // The same parameters, with or without checks.
+    let read = read_parameters::<Bls12>(&path, true)?;
+    assert!(read == params);
+    let mapped = MmapParameters::<Bls12>::open(&path, true)?;
+    assert!(mapped.vk == params.vk);
+    assert_eq!(mapped.h.len, params.h.len());
+    assert_eq!(mapped.b_g2.len, params.b_g2.len());
+
+    // A proof from the mapped parameters.
+    let proof = create_random_proof(circuit, &mapped, &mut rng)?;
+    let inputs = [Scalar::from(0x0201)];
+    assert!(verify_proof(&prepare_verifying_key(&params.vk), &proof, &inputs).is_ok());
+
+    // A point off the subgroup or the curve is detected by checked loading only.
+    let mut bytes = std::fs::read(&path)?;
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    let bad = dir.join("bad-pk");
+    File::create(&bad)?.write_all(&bytes)?;
+    assert!(read_parameters::<Bls12>(&bad, true).is_err());
+    assert!(read_parameters::<Bls12>(&bad, false).is_ok());
+    assert!(MmapParameters::<Bls12>::open(&bad, true).is_err());
+    assert!(MmapParameters::<Bls12>::open(&bad, false).is_ok());
+
+    write(&bad, &bytes[..last])?;
+    assert!(MmapParameters::<Bls12>::open(&bad, false).is_err());
+    Ok(())
+}
@@ -0,0 +1,280 @@
+//! Run any executable that speaks zkInterface as a gadget for call_gadget.
+
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use zkinterface::{Reader, Result};
+use crate::compression::{decompress, list_workspace_files, read_file};
+use crate::json::push_messages;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How the call and the response are exchanged with the program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    /// Write the call message into stdin, and read the response messages from stdout.
+    Stdio,
+    /// Write the call message into `call.zkif` in a fresh temporary directory,
+    /// and read all other zkInterface files that the program writes there.
+    /// The program runs in that directory, and the argument `{workspace}` is replaced by its path.
+    Workspace,
+}
+
+/// A gadget implemented by an external program.
+///
+/// # Example
+/// ```no_run
+/// # use zkinterface_bellman::process_gadget::ProcessGadget;
+/// # use std::time::Duration;
+/// let mut gadget = ProcessGadget::new("my_gadget");
+/// gadget.args = vec!["--backend".to_string(), "zkinterface".to_string()];
+/// gadget.timeout = Some(Duration::from_secs(60));
+/// // call_gadget(cs, &inputs, &gadget.exec_fn())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ProcessGadget {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Environment variables to add to the environment of the current process.
+    pub env: Vec<(String, String)>,
+    /// Kill the program if it runs for longer than this.
+    pub timeout: Option<Duration>,
+    pub transport: Transport,
+}
+
+impl ProcessGadget {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        ProcessGadget {
+            program: program.into(),
+            args: vec![],
+            env: vec![],
+            timeout: None,
+            transport: Transport::Stdio,
+        }
+    }
+
+    /// The function to give to call_gadget.
+    pub fn exec_fn(&self) -> impl Fn(&[u8]) -> Result<Reader> + '_ {
+        move |call_msg| self.exec(call_msg)
+    }
+
+    /// Run the program with a call message, and collect its response messages.
+    pub fn exec(&self, call_msg: &[u8]) -> Result<Reader> {
+        match self.transport {
+            Transport::Stdio => self.exec_stdio(call_msg),
+            Transport::Workspace => self.exec_workspace(call_msg),
+        }
+    }
+
+    fn exec_stdio(&self, call_msg: &[u8]) -> Result<Reader> {
+        let mut cmd = self.command(&self.args);
+        cmd.stdin(Stdio::piped());
+
+        let stdout = self.run(cmd, Some(call_msg))?;
+
+        // Accept the same forms as files: compressed or not, binary or JSON.
+        let mut buf = vec![];
+        decompress(&stdout[..])?.read_to_end(&mut buf)?;
+        let mut reader = Reader::new();
+        push_messages(&mut reader, buf)?;
+        Ok(reader)
+    }
+
+    fn exec_workspace(&self, call_msg: &[u8]) -> Result<Reader> {
+        let workspace = tempfile::tempdir()?;
+        let call_path = workspace.path().join("call.zkif");
+        File::create(&call_path)?.write_all(call_msg)?;
+
+        let workspace_arg = workspace.path().to_str().ok_or("Invalid temporary directory")?;
+        let args: Vec<String> = self.args.iter()
+            .map(|arg| arg.replace("{workspace}", workspace_arg))
+            .collect();
+
+        let mut cmd = self.command(&args);
+        cmd.current_dir(workspace.path());
+        cmd.stdin(Stdio::null());
+
+        self.run(cmd, None)?;
+
+        let mut reader = Reader::new();
+        for path in list_workspace_files(&[workspace.path().to_path_buf()])? {
+            if path != call_path {
+                read_file(&mut reader, path)?;
+            }
+        }
+        Ok(reader)
+    }
+
+    fn command(&self, args: &[String]) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(args);
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        cmd
+    }
+
+    /// Run the command to completion and return its stdout.
+    fn run(&self, mut cmd: Command, stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut child = cmd.spawn()
+            .map_err(|err| format!("Cannot run gadget {}: {}", self.program.display(), err))?;
+
+        // Feed and drain the pipes in threads, so that a large message does not block the program.
+        let stdin_thread = match (stdin, child.stdin.take()) {
+            (Some(data), Some(mut pipe)) => {
+                let data = data.to_vec();
+                Some(thread::spawn(move || pipe.write_all(&data)))
+            }
+            _ => None,
+        };
+        let stdout_pipe = drain(child.stdout.take());
+        let stderr_pipe = drain(child.stderr.take());
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let status = self.wait(&mut child, deadline)?;
+
+        // A subprocess of the program may keep the pipes open after it exits, so do not wait past the deadline.
+        let stdout = self.collect(&stdout_pipe, deadline)?;
+        let stderr = self.collect(&stderr_pipe, deadline)?;
+        // A program may legitimately exit without reading its input, so write errors are ignored,
+        // and the writing thread is not joined, in case a subprocess holds the pipe.
+        drop(stdin_thread);
+
+        if !status.success() {
+            return Err(format!("Gadget {} failed ({}): {}",
+                               self.program.display(), status, String::from_utf8_lossy(&stderr).trim()).into());
+        }
+        Ok(stdout)
+    }
+
+    fn wait(&self, child: &mut Child, deadline: Option<Instant>) -> Result<std::process::ExitStatus> {
+        let deadline = match deadline {
+            None => return Ok(child.wait()?),
+            Some(deadline) => deadline,
+        };
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(self.timed_out());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Wait for the content of a pipe until the deadline. The reading thread is left behind after a timeout.
+    fn collect(&self, pipe: &Receiver<Vec<u8>>, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let received = match deadline {
+            None => pipe.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(deadline) => pipe.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        };
+        match received {
+            Ok(buf) => Ok(buf),
+            Err(RecvTimeoutError::Timeout) => Err(self.timed_out()),
+            Err(RecvTimeoutError::Disconnected) => Err("Failed to read the output of the gadget".into()),
+        }
+    }
+
+    fn timed_out(&self) -> Box<dyn std::error::Error> {
+        format!("Gadget {} timed out after {:?}", self.program.display(), self.timeout.unwrap_or_default()).into()
+    }
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = sender.send(buf);
+    });
+    receiver
+}
+
+
+#[test]
+#[cfg(unix)]
+fn test_process_gadget() -> Result<()> {
+    use std::fs::{create_dir_all, read};
+    use bellman::ConstraintSystem;
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::{call_gadget, example_gadget};
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![
+        AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?,
+        AllocatedNum::alloc(cs.namespace(|| "y"), || Ok(Scalar::from(4)))?,
+    ];
+
+    // Prepare the response of a program, using the example gadget.
+    let dir = PathBuf::from("local/test_process_gadget");
+    create_dir_all(&dir)?;
+    let response_path = dir.join("response.zkif");
+    let call_path = dir.join("call.zkif");
+    let record_gadget = |call_msg: &[u8]| {
+        let response = example_gadget(call_msg)?;
+        File::create(&response_path)?.write_all(&response.messages[0])?;
+        Ok(response)
+    };
+    let expected = call_gadget(&mut cs.namespace(|| "example"), &inputs, &record_gadget)?;
+
+    // Exchange messages through stdin and stdout.
+    let mut gadget = ProcessGadget::new("sh");
+    gadget.args = vec!["-c".to_string(), "cat > \"$CALL\" && cat \"$RESPONSE\"".to_string()];
+    gadget.env = vec![
+        ("CALL".to_string(), call_path.to_str().unwrap().to_string()),
+        ("RESPONSE".to_string(), response_path.to_str().unwrap().to_string()),
+    ];
+    let outputs = call_gadget(&mut cs.namespace(|| "stdio"), &inputs, &gadget.exec_fn())?;
+    assert_eq!(outputs[0].get_value(), expected[0].get_value());
+    assert!(!read(&call_path)?.is_empty());
+
+    // A compressed response.
+    let compressed_path = dir.join("response.zkif.gz");
+    let mut encoder = flate2::write::GzEncoder::new(File::create(&compressed_path)?, flate2::Compression::default());
+    encoder.write_all(&read(&response_path)?)?;
+    encoder.finish()?;
+    gadget.env[1].1 = compressed_path.to_str().unwrap().to_string();
+    let outputs = call_gadget(&mut cs.namespace(|| "stdio compressed"), &inputs, &gadget.exec_fn())?;
+    assert_eq!(outputs[0].get_value(), expected[0].get_value());
+
+    // Exchange messages through a temporary workspace.
+    gadget.transport = Transport::Workspace;
+    gadget.env = vec![("RESPONSE".to_string(), std::fs::canonicalize(&response_path)?.to_str().unwrap().to_string())];
+    gadget.args = vec!["-c".to_string(), "test -f {workspace}/call.zkif && cp \"$RESPONSE\" response.zkif".to_string()];
+    let outputs = call_gadget(&mut cs.namespace(|| "workspace"), &inputs, &gadget.exec_fn())?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(25)));
+
+    assert!(cs.is_satisfied());
+
+    // Errors include the output of the program.
+    gadget.args = vec!["-c".to_string(), "echo 'Unknown gadget' >&2; exit 3".to_string()];
+    let err = gadget.exec(&[]).err().unwrap();
+    assert!(err.to_string().contains("Unknown gadget"));
+
+    gadget.args = vec!["-c".to_string(), "sleep 10".to_string()];
+    gadget.timeout = Some(Duration::from_millis(100));
+    let start = Instant::now();
+    let err = gadget.exec(&[]).err().unwrap();
+    assert!(err.to_string().contains("timed out"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // A subprocess that keeps the output open does not block past the timeout.
+    gadget.args = vec!["-c".to_string(), "sleep 10 & exit 0".to_string()];
+    let start = Instant::now();
+    let err = gadget.exec(&[]).err().unwrap();
+    assert!(err.to_string().contains("timed out"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    Ok(())
+}
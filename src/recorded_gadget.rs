@@ -0,0 +1,147 @@
+//! Record the calls and responses of gadgets, and replay them without the gadget.
+
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zkinterface::{Reader, Result};
+use crate::compression::read_file;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RecordMode {
+    /// Run the gadget, and store the call and the response.
+    Record,
+    /// Serve the stored responses without running the gadget. Unknown calls fail.
+    Replay,
+    /// Serve the stored response if there is one, otherwise run the gadget and store it.
+    ReplayOrRecord,
+}
+
+/// Wrap the exec_fn of a gadget to record or replay its responses.
+///
+/// Each call is stored in a directory of the store named after the BLAKE2s hash of the call message,
+/// containing `call.zkif` and `response.zkif`.
+///
+/// # Example
+/// ```no_run
+/// # use zkinterface_bellman::process_gadget::ProcessGadget;
+/// # use zkinterface_bellman::recorded_gadget::{RecordedGadget, RecordMode};
+/// let gadget = ProcessGadget::new("my_gadget");
+/// let recorder = RecordedGadget::new("tests/gadget_calls", RecordMode::Record, gadget.exec_fn());
+/// // call_gadget(cs, &inputs, &recorder.exec_fn())
+///
+/// // Later, without my_gadget installed:
+/// let replayer = RecordedGadget::replay("tests/gadget_calls");
+/// // call_gadget(cs, &inputs, &replayer.exec_fn())
+/// ```
+pub struct RecordedGadget<F> {
+    pub store: PathBuf,
+    pub mode: RecordMode,
+    exec_fn: F,
+}
+
+impl<F: Fn(&[u8]) -> Result<Reader>> RecordedGadget<F> {
+    pub fn new(store: impl AsRef<Path>, mode: RecordMode, exec_fn: F) -> Self {
+        RecordedGadget {
+            store: store.as_ref().to_path_buf(),
+            mode,
+            exec_fn,
+        }
+    }
+
+    /// The function to give to call_gadget.
+    pub fn exec_fn(&self) -> impl Fn(&[u8]) -> Result<Reader> + '_ {
+        move |call_msg| self.exec(call_msg)
+    }
+
+    pub fn exec(&self, call_msg: &[u8]) -> Result<Reader> {
+        let dir = self.call_dir(call_msg);
+        let response_path = dir.join("response.zkif");
+
+        if self.mode != RecordMode::Record && response_path.exists() {
+            let mut reader = Reader::new();
+            read_file(&mut reader, &response_path)?;
+            return Ok(reader);
+        }
+
+        if self.mode == RecordMode::Replay {
+            return Err(format!(
+                "No recorded response for this call (expected {}). Record it with the gadget available.",
+                response_path.display()).into());
+        }
+
+        let response = (self.exec_fn)(call_msg)?;
+
+        create_dir_all(&dir)?;
+        File::create(dir.join("call.zkif"))?.write_all(call_msg)?;
+        let mut file = File::create(&response_path)?;
+        for msg in &response.messages {
+            file.write_all(msg)?;
+        }
+
+        Ok(response)
+    }
+
+    /// The directory of a call in the store.
+    pub fn call_dir(&self, call_msg: &[u8]) -> PathBuf {
+        self.store.join(blake2s_simd::blake2s(call_msg).to_hex().as_str())
+    }
+}
+
+impl RecordedGadget<fn(&[u8]) -> Result<Reader>> {
+    /// Replay recorded responses, without any gadget to run.
+    pub fn replay(store: impl AsRef<Path>) -> Self {
+        fn no_gadget(_: &[u8]) -> Result<Reader> {
+            Err("No gadget to run in replay mode.".into())
+        }
+        RecordedGadget::new(store, RecordMode::Replay, no_gadget)
+    }
+}
+
+
+#[test]
+fn test_recorded_gadget() -> Result<()> {
+    use std::cell::Cell;
+    use std::fs::remove_dir_all;
+    use bellman::ConstraintSystem;
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::{call_gadget, example_gadget};
+
+    let store = PathBuf::from("local/test_recorded_gadget");
+    let _ = remove_dir_all(&store);
+
+    let calls = Cell::new(0);
+    let counting_gadget = |call_msg: &[u8]| {
+        calls.set(calls.get() + 1);
+        example_gadget(call_msg)
+    };
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let x = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+    let y = vec![AllocatedNum::alloc(cs.namespace(|| "y"), || Ok(Scalar::from(4)))?];
+
+    let recorder = RecordedGadget::new(&store, RecordMode::Record, counting_gadget);
+    call_gadget(&mut cs.namespace(|| "record"), &x, &recorder.exec_fn())?;
+    assert_eq!(calls.get(), 1);
+
+    let replayer = RecordedGadget::replay(&store);
+    let outputs = call_gadget(&mut cs.namespace(|| "replay"), &x, &replayer.exec_fn())?;
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(9)));
+    assert!(cs.is_satisfied());
+
+    // Another call is unknown.
+    match call_gadget(&mut cs.namespace(|| "unknown"), &y, &replayer.exec_fn()) {
+        Err(err) => assert!(err.to_string().contains("No recorded response")),
+        Ok(_) => panic!("Should refuse an unknown call"),
+    }
+
+    // Only unknown calls are run.
+    let recorder = RecordedGadget::new(&store, RecordMode::ReplayOrRecord, counting_gadget);
+    call_gadget(&mut cs.namespace(|| "known"), &x, &recorder.exec_fn())?;
+    call_gadget(&mut cs.namespace(|| "new"), &y, &recorder.exec_fn())?;
+    assert_eq!(calls.get(), 2);
+    call_gadget(&mut cs.namespace(|| "replay new"), &y, &replayer.exec_fn())?;
+    assert!(cs.is_satisfied());
+
+    Ok(())
+}
@@ -6,26 +6,31 @@ use bellman::{
         create_random_proof,
         prepare_verifying_key,
         verify_proof,
-        Parameters,
+        ParameterSource,
         Proof,
+        VerifyingKey,
     },
     SynthesisError,
     Variable,
     gadgets::num::AllocatedNum,
     gadgets::test::TestConstraintSystem,
 };
-use rand;
+use rand::{self, RngCore};
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 use super::import::{enforce, read_scalar};
+use super::parameters::{KeyOptions, MmapParameters, read_parameters};
+use super::compressed_inputs::{CompressedInputsCircuit, compress_public_inputs};
 pub use zkinterface::Reader;
 use std::error::Error;
-use ff::PrimeField;
+use ff::{Field, PrimeField};
+use group::Curve;
+use pairing::Engine;
 use bls12_381::{Bls12, Scalar as Bls12Scalar};
 
-const DEFAULT_KEY_PATH: &str = "bellman-pk";
-const DEFAULT_PROOF_PATH: &str = "bellman-proof";
+pub(crate) const DEFAULT_KEY_PATH: &str = "bellman-pk";
+pub(crate) const DEFAULT_PROOF_PATH: &str = "bellman-proof";
 
 
 /// A circuit instance built from zkif messages.
@@ -37,62 +42,78 @@ pub struct ZKIFCircuit<'a> {
 impl<'a, Scalar: PrimeField> Circuit<Scalar> for ZKIFCircuit<'a> {
     fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError>
     {
-        // Check that we are working on the right field.
-        match self.reader.first_header().unwrap().field_maximum() {
-            None => {
-                eprintln!("Warning: no field_maximum specified in messages, the field may be incompatible.");
-            }
-            Some(field_maximum) => {
-                let requested: Scalar = read_scalar(field_maximum);
-                let supported: Scalar = Scalar::one().neg();
-                if requested != supported {
-                    eprintln!("Error: This proving system does not support the field specified for this circuit.");
-                    eprintln!("Requested field: {:?}", requested);
-                    eprintln!("Supported field: {:?}", supported);
-                    panic!();
-                }
+        synthesize_zkif(self.reader, cs, true)?;
+        Ok(())
+    }
+}
+
+/// Synthesize the circuit of the messages, and return the instance variables.
+/// They are made public inputs if `inputize` is true, otherwise they stay private.
+pub(crate) fn synthesize_zkif<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
+    reader: &Reader,
+    cs: &mut CS,
+    inputize: bool,
+) -> Result<Vec<AllocatedNum<Scalar>>, SynthesisError>
+{
+    // Check that we are working on the right field.
+    match reader.first_header().unwrap().field_maximum() {
+        None => {
+            eprintln!("Warning: no field_maximum specified in messages, the field may be incompatible.");
+        }
+        Some(field_maximum) => {
+            let requested: Scalar = read_scalar(field_maximum);
+            let supported: Scalar = Scalar::one().neg();
+            if requested != supported {
+                eprintln!("Error: This proving system does not support the field specified for this circuit.");
+                eprintln!("Requested field: {:?}", requested);
+                eprintln!("Supported field: {:?}", supported);
+                panic!();
             }
         }
+    }
 
-        // Track variables by id. Used to convert constraints.
-        let mut id_to_var = HashMap::<u64, Variable>::new();
+    // Track variables by id. Used to convert constraints.
+    let mut id_to_var = HashMap::<u64, Variable>::new();
 
-        id_to_var.insert(0, CS::one());
+    id_to_var.insert(0, CS::one());
 
-        // Allocate public inputs, with optional values.
-        let public_vars = self.reader.instance_variables().unwrap();
+    // Allocate public inputs, with optional values.
+    let public_vars = reader.instance_variables().unwrap();
+    let mut instance = Vec::with_capacity(public_vars.len());
 
-        for var in public_vars {
-            let mut cs = cs.namespace(|| format!("public_{}", var.id));
-            let num = AllocatedNum::alloc(&mut cs, || {
-                Ok(read_scalar(var.value))
-            })?;
+    for var in public_vars {
+        let mut cs = cs.namespace(|| format!("public_{}", var.id));
+        let num = AllocatedNum::alloc(&mut cs, || {
+            Ok(read_scalar(var.value))
+        })?;
 
+        if inputize {
             num.inputize(&mut cs)?;
-
-            // Track input variable.
-            id_to_var.insert(var.id, num.get_variable());
         }
 
-        // Allocate private variables, with optional values.
-        let private_vars = self.reader.private_variables().unwrap();
+        // Track input variable.
+        id_to_var.insert(var.id, num.get_variable());
+        instance.push(num);
+    }
 
-        for var in private_vars {
-            let num = AllocatedNum::alloc(
-                cs.namespace(|| format!("private_{}", var.id)), || {
-                    Ok(read_scalar(var.value))
-                })?;
+    // Allocate private variables, with optional values.
+    let private_vars = reader.private_variables().unwrap();
 
-            // Track private variable.
-            id_to_var.insert(var.id, num.get_variable());
-        };
+    for var in private_vars {
+        let num = AllocatedNum::alloc(
+            cs.namespace(|| format!("private_{}", var.id)), || {
+                Ok(read_scalar(var.value))
+            })?;
 
-        for (i, constraint) in self.reader.iter_constraints().enumerate() {
-            enforce(&mut cs.namespace(|| format!("constraint_{}", i)), &id_to_var, &constraint);
-        }
+        // Track private variable.
+        id_to_var.insert(var.id, num.get_variable());
+    };
 
-        Ok(())
+    for (i, constraint) in reader.iter_constraints().enumerate() {
+        enforce(&mut cs.namespace(|| format!("constraint_{}", i)), &id_to_var, &constraint);
     }
+
+    Ok(instance)
 }
 
 
@@ -127,15 +148,24 @@ pub fn setup(
     workspace: &Path,
 ) -> Result<(), Box<dyn Error>>
 {
-    let key_path = workspace.join(DEFAULT_KEY_PATH);
+    setup_with_options(reader, workspace, &KeyOptions::default())
+}
 
-    let circuit = ZKIFCircuit { reader };
+/// Like setup, for the circuit chosen by the options.
+pub fn setup_with_options(
+    reader: &Reader,
+    workspace: &Path,
+    key_options: &KeyOptions,
+) -> Result<(), Box<dyn Error>>
+{
+    let key_path = workspace.join(DEFAULT_KEY_PATH);
 
     let mut rng = rand::thread_rng();
-    let params = generate_random_parameters::<Bls12, _, _>(
-        circuit.clone(),
-        &mut rng,
-    )?;
+    let params = if key_options.compress_inputs {
+        generate_random_parameters::<Bls12, _, _>(CompressedInputsCircuit { reader }, &mut rng)?
+    } else {
+        generate_random_parameters::<Bls12, _, _>(ZKIFCircuit { reader }, &mut rng)?
+    };
 
     // Store params.
     let file = File::create(&key_path)?;
@@ -149,26 +179,29 @@ pub fn prove(
     reader: &Reader,
     workspace: &Path,
 ) -> Result<(), Box<dyn Error>>
+{
+    prove_with_options(reader, workspace, &KeyOptions::default())
+}
+
+/// Like prove, loading the proving key with checks or memory-mapped.
+pub fn prove_with_options(
+    reader: &Reader,
+    workspace: &Path,
+    key_options: &KeyOptions,
+) -> Result<(), Box<dyn Error>>
 {
     let key_path = workspace.join(DEFAULT_KEY_PATH);
     let proof_path = workspace.join(DEFAULT_PROOF_PATH);
 
-    let circuit = ZKIFCircuit { reader };
-
-    // Load params.
-    let params = {
-        eprintln!("Reading parameters from {}", key_path.display());
-        let mut file = File::open(&key_path)?;
-        Parameters::<Bls12>::read(&mut file, false)?
+    eprintln!("Reading parameters from {}", key_path.display());
+    let proof = if key_options.mmap {
+        let params = MmapParameters::<Bls12>::open(&key_path, key_options.checked)?;
+        prove_circuit(reader, &params, key_options)?
+    } else {
+        let params = read_parameters::<Bls12>(&key_path, key_options.checked)?;
+        prove_circuit(reader, &params, key_options)?
     };
 
-    let mut rng = rand::thread_rng();
-    let proof = create_random_proof(
-        circuit,
-        &params,
-        &mut rng,
-    )?;
-
     // Store proof.
     let file = File::create(&proof_path)?;
     proof.write(file)?;
@@ -177,30 +210,38 @@ pub fn prove(
     Ok(())
 }
 
+/// Prove the circuit of the messages, with compressed inputs if chosen by the options.
+pub(crate) fn prove_circuit<P: ParameterSource<Bls12>>(
+    reader: &Reader,
+    params: P,
+    key_options: &KeyOptions,
+) -> Result<Proof<Bls12>, SynthesisError> {
+    let mut rng = rand::thread_rng();
+    if key_options.compress_inputs {
+        create_random_proof(CompressedInputsCircuit { reader }, params, &mut rng)
+    } else {
+        create_random_proof(ZKIFCircuit { reader }, params, &mut rng)
+    }
+}
+
 pub fn verify(
     reader: &Reader,
     workspace: &Path,
+) -> Result<(), Box<dyn Error>> {
+    verify_with_options(reader, workspace, &KeyOptions::default())
+}
+
+/// Like verify, loading the key with checks or memory-mapped. Only the verifying key is decoded from a mapped key.
+pub fn verify_with_options(
+    reader: &Reader,
+    workspace: &Path,
+    key_options: &KeyOptions,
 ) -> Result<(), Box<dyn Error>> {
     let key_path = workspace.join(DEFAULT_KEY_PATH);
     let proof_path = workspace.join(DEFAULT_PROOF_PATH);
 
-    let pvk = {
-        eprintln!("Reading parameters from {}", key_path.display());
-        let mut file = File::open(&key_path)?;
-        let params = Parameters::<Bls12>::read(&mut file, false)?;
-        prepare_verifying_key::<Bls12>(&params.vk)
-    };
-
-    let public_inputs: Vec<Bls12Scalar> = {
-        match reader.instance_variables() {
-            None => Vec::new(),
-            Some(instance_variables) => {
-                instance_variables.iter().map(|var|
-                    read_scalar(var.value)
-                ).collect()
-            }
-        }
-    };
+    let pvk = prepare_verifying_key::<Bls12>(&read_verifying_key(&key_path, key_options)?);
+    let public_inputs = public_inputs(reader, key_options)?;
 
     let proof = {
         eprintln!("Reading proof from {}", proof_path.display());
@@ -217,6 +258,81 @@ pub fn verify(
     Ok(())
 }
 
+/// Re-randomize a proof into a new proof of the same statement, which cannot be linked to the original.
+///
+/// With random r1 ≠ 0 and r2: A' = A / r1, B' = r1·B + r1·r2·δ, C' = C + r2·A.
+/// This does not require the witness, and a valid proof stays valid.
+pub fn rerandomize_proof<E: Engine, R: RngCore>(
+    vk: &VerifyingKey<E>,
+    proof: &Proof<E>,
+    rng: &mut R,
+) -> Proof<E> {
+    let r1 = loop {
+        let r1 = E::Fr::random(&mut *rng);
+        if !r1.is_zero() { break r1; }
+    };
+    let r2 = E::Fr::random(&mut *rng);
+
+    Proof {
+        a: (proof.a * r1.invert().unwrap()).to_affine(),
+        b: (proof.b * r1 + vk.delta_g2 * (r1 * r2)).to_affine(),
+        c: (proof.a * r2 + proof.c).to_affine(),
+    }
+}
+
+/// Re-randomize the proof in the workspace, and replace it after checking that the new proof is valid
+/// for the instance variables of the messages.
+pub fn rerandomize(
+    reader: &Reader,
+    workspace: &Path,
+    key_options: &KeyOptions,
+) -> Result<(), Box<dyn Error>> {
+    let key_path = workspace.join(DEFAULT_KEY_PATH);
+    let proof_path = workspace.join(DEFAULT_PROOF_PATH);
+
+    let vk = read_verifying_key(&key_path, key_options)?;
+    let proof = {
+        eprintln!("Reading proof from {}", proof_path.display());
+        Proof::<Bls12>::read(File::open(&proof_path)?)?
+    };
+
+    let new_proof = rerandomize_proof(&vk, &proof, &mut rand::thread_rng());
+
+    if verify_proof(&prepare_verifying_key(&vk), &new_proof, &public_inputs(reader, key_options)?).is_err() {
+        return Err("The re-randomized proof is not valid for this instance; the proof is left unchanged.".into());
+    }
+
+    new_proof.write(File::create(&proof_path)?)?;
+    eprintln!("Written re-randomized proof into {}", proof_path.display());
+    Ok(())
+}
+
+fn read_verifying_key(key_path: &Path, key_options: &KeyOptions) -> Result<VerifyingKey<Bls12>, Box<dyn Error>> {
+    eprintln!("Reading parameters from {}", key_path.display());
+    Ok(if key_options.mmap {
+        MmapParameters::<Bls12>::open(key_path, key_options.checked)?.vk
+    } else {
+        read_parameters::<Bls12>(key_path, key_options.checked)?.vk
+    })
+}
+
+fn public_inputs(reader: &Reader, key_options: &KeyOptions) -> Result<Vec<Bls12Scalar>, Box<dyn Error>> {
+    let values = match reader.instance_variables() {
+        None => Vec::new(),
+        Some(instance_variables) => {
+            instance_variables.iter().map(|var|
+                read_scalar(var.value)
+            ).collect()
+        }
+    };
+
+    if key_options.compress_inputs {
+        Ok(compress_public_inputs(&values)?)
+    } else {
+        Ok(values)
+    }
+}
+
 
 #[test]
 fn test_zkif_backend() -> Result<(), Box<dyn Error>> {
@@ -236,5 +352,15 @@ fn test_zkif_backend() -> Result<(), Box<dyn Error>> {
 
     verify(&reader, out_dir)?;
 
+    let options = KeyOptions { checked: true, mmap: true, ..KeyOptions::default() };
+    prove_with_options(&reader, out_dir, &options)?;
+    verify_with_options(&reader, out_dir, &options)?;
+
+    // A re-randomized proof is different and valid.
+    let original = std::fs::read(out_dir.join(DEFAULT_PROOF_PATH))?;
+    rerandomize(&reader, out_dir, &KeyOptions::default())?;
+    assert_ne!(std::fs::read(out_dir.join(DEFAULT_PROOF_PATH))?, original);
+    verify(&reader, out_dir)?;
+
     Ok(())
 }
@@ -1,16 +1,18 @@
 use std::path::Path;
 use std::marker::PhantomData;
+use std::collections::VecDeque;
 
-use zkinterface::{ConstraintSystem, Witness, Variables, KeyValue, StatementBuilder, Sink, WorkspaceSink, BilinearConstraint};
+use zkinterface::{CircuitHeader, ConstraintSystem, Witness, Variables, KeyValue, StatementBuilder, Sink, WorkspaceSink, BilinearConstraint};
 use bellman as bl;
 use bellman::{Variable, Index, LinearCombination, SynthesisError};
 use ff::PrimeField;
-use super::export::{write_scalar, to_zkif_constraint};
+use super::compression::{Compression, CompressedWorkspaceSink};
+use super::export::{write_scalar, to_zkid, to_zkif_constraint, to_zkif_constraint_raw, field_maximum};
 use std::mem;
 
-const DEFAULT_CONSTRAINTS_PER_MESSAGE: usize = 100000;
+pub const DEFAULT_CONSTRAINTS_PER_MESSAGE: usize = 100000;
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Target {
     /// Generate constraints, public inputs, witness.
     Prover,
@@ -20,14 +22,22 @@ pub enum Target {
     Preprocessing,
 }
 
-pub struct ZkifCS<Scalar: PrimeField> {
+pub struct ZkifCS<Scalar: PrimeField, S: Sink = WorkspaceSink> {
     pub constraints_per_message: usize,
+    /// Keep linear combinations as produced by bellman, instead of normalizing them.
+    pub raw_linear_combinations: bool,
 
-    statement: StatementBuilder<WorkspaceSink>,
+    statement: StatementBuilder<S>,
     constraints: ConstraintSystem,
     target: Target,
     witness_ids: Vec<u64>,
     witness_encoding: Vec<u8>,
+    /// The ids of the inputs of a gadget call, given to the first allocations.
+    call_input_ids: VecDeque<u64>,
+    /// The free_variable_id of a gadget call, from which outputs are allocated.
+    call_free_variable_id: u64,
+    num_constraints: usize,
+    num_constraint_messages: usize,
     phantom: PhantomData<Scalar>,
 }
 
@@ -35,21 +45,91 @@ impl<Scalar: PrimeField> ZkifCS<Scalar> {
     /// Must call finish() to finalize the files in the workspace.
     pub fn new(workspace: impl AsRef<Path>, target: Target) -> Self {
         let sink = WorkspaceSink::new(workspace).unwrap();
+        Self::from_sink(sink, target)
+    }
+}
+
+impl<Scalar: PrimeField> ZkifCS<Scalar, CompressedWorkspaceSink> {
+    /// Write compressed files into the workspace.
+    /// Must call finish_compressed() to finalize the files in the workspace.
+    pub fn new_compressed(workspace: impl AsRef<Path>, target: Target, compression: Compression) -> zkinterface::Result<Self> {
+        let sink = CompressedWorkspaceSink::new(workspace, compression)?;
+        Ok(Self::from_sink(sink, target))
+    }
+
+    /// Like finish(), and complete the compressed witness file.
+    pub fn finish_compressed(self, name: &str) -> zkinterface::Result<()> {
+        self.finish_into_sink(name)?.finish()
+    }
+}
+
+impl<Scalar: PrimeField, S: Sink> ZkifCS<Scalar, S> {
+    /// Write the messages into any Sink.
+    /// Must call finish() to finalize the statement.
+    pub fn from_sink(sink: S, target: Target) -> Self {
         let statement = StatementBuilder::new(sink);
 
         ZkifCS {
             constraints_per_message: DEFAULT_CONSTRAINTS_PER_MESSAGE,
+            raw_linear_combinations: false,
             statement,
             constraints: ConstraintSystem::default(),
             target,
             witness_ids: vec![],
             witness_encoding: vec![],
+            call_input_ids: VecDeque::new(),
+            call_free_variable_id: 0,
+            num_constraints: 0,
+            num_constraint_messages: 0,
             phantom: PhantomData,
         }
     }
 
-    pub fn finish(mut self, name: &str) -> zkinterface::Result<()> {
-        if self.constraints.constraints.len() > 0 {
+    /// Synthesize a gadget in response to a zkInterface call. Must call finish_gadget() to write the response.
+    ///
+    /// The first variables allocated with alloc() take the ids of the inputs of the call, in order,
+    /// and their values are not repeated in the witness.
+    /// Other variables are allocated from the free_variable_id of the call.
+    pub fn from_call(sink: S, target: Target, call: &CircuitHeader) -> Self {
+        let mut cs = Self::from_sink(sink, target);
+        cs.statement.header.free_variable_id = call.free_variable_id;
+        cs.call_input_ids = call.instance_variables.variable_ids.iter().cloned().collect();
+        cs.call_free_variable_id = call.free_variable_id;
+        cs
+    }
+
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// The number of constraints enforced so far.
+    pub fn num_constraints(&self) -> usize {
+        self.num_constraints
+    }
+
+    /// The number of ConstraintSystem messages written so far, including the pending one.
+    pub fn num_constraint_messages(&self) -> usize {
+        self.num_constraint_messages + if self.constraints.constraints.is_empty() { 0 } else { 1 }
+    }
+
+    /// The number of instance variables, excluding the constant one.
+    pub fn num_instance_variables(&self) -> usize {
+        self.statement.header.instance_variables.variable_ids.len()
+    }
+
+    /// The number of private variables.
+    pub fn num_private_variables(&self) -> usize {
+        (self.statement.header.free_variable_id - 1) as usize - self.num_instance_variables()
+    }
+
+    pub fn finish(self, name: &str) -> zkinterface::Result<()> {
+        self.finish_into_sink(name)?;
+        Ok(())
+    }
+
+    /// Like finish(), and give back the sink.
+    pub fn finish_into_sink(mut self, name: &str) -> zkinterface::Result<S> {
+        if !self.constraints.constraints.is_empty() {
             self.statement.push_constraints(self.constraints)?;
         }
 
@@ -63,11 +143,7 @@ impl<Scalar: PrimeField> ZkifCS<Scalar> {
             self.statement.push_witness(wit)?;
         }
 
-        let negative_one = Scalar::one().neg();
-        let mut field_maximum = Vec::<u8>::new();
-        write_scalar(&negative_one, &mut field_maximum);
-
-        self.statement.header.field_maximum = Some(field_maximum);
+        self.statement.header.field_maximum = Some(field_maximum::<Scalar>());
         self.statement.header.configuration = Some(vec![
             KeyValue {
                 key: "name".to_string(),
@@ -75,27 +151,96 @@ impl<Scalar: PrimeField> ZkifCS<Scalar> {
                 data: None,
                 number: 0,
             }]);
-        self.statement.finish_header()
+        self.statement.finish_header()?;
+        Ok(self.statement.sink)
+    }
+
+    /// Finish the response of a gadget started with from_call(): constraints, witness of the local variables,
+    /// and a header with the outputs and their values. Give back the sink.
+    pub fn finish_gadget(mut self, outputs: &[Variable]) -> zkinterface::Result<S> {
+        if !self.call_input_ids.is_empty() {
+            return Err(format!("The gadget did not allocate its {} last inputs.", self.call_input_ids.len()).into());
+        }
+
+        if !self.constraints.constraints.is_empty() {
+            self.statement.push_constraints(self.constraints)?;
+        }
+
+        let output_ids: Vec<u64> = outputs.iter().map(to_zkid).collect();
+        for (i, id) in output_ids.iter().enumerate() {
+            if *id < self.call_free_variable_id || output_ids[..i].contains(id) {
+                return Err(format!("The output {} of the gadget is not a new variable.", id).into());
+            }
+        }
+
+        let output_values = if self.target == Target::Prover {
+            // Move the values of the outputs from the witness to the header.
+            let value_size = if self.witness_ids.is_empty() { 0 } else { self.witness_encoding.len() / self.witness_ids.len() };
+            let mut witness_ids = vec![];
+            let mut witness_encoding = vec![];
+            let mut values = vec![vec![]; output_ids.len()];
+
+            for (id, value) in self.witness_ids.iter().zip(self.witness_encoding.chunks(value_size.max(1))) {
+                match output_ids.iter().position(|out| out == id) {
+                    Some(i) => values[i] = value.to_vec(),
+                    None => {
+                        witness_ids.push(*id);
+                        witness_encoding.extend_from_slice(value);
+                    }
+                }
+            }
+
+            if let Some(i) = values.iter().position(|v| v.is_empty()) {
+                return Err(format!("The output {} of the gadget is not a new variable.", output_ids[i]).into());
+            }
+
+            self.statement.push_witness(Witness {
+                assigned_variables: Variables {
+                    variable_ids: witness_ids,
+                    values: Some(witness_encoding),
+                }
+            })?;
+            Some(values.concat())
+        } else {
+            None
+        };
+
+        self.statement.push_header(CircuitHeader {
+            instance_variables: Variables {
+                variable_ids: output_ids,
+                values: output_values,
+            },
+            free_variable_id: self.statement.header.free_variable_id,
+            field_maximum: Some(field_maximum::<Scalar>()),
+            configuration: None,
+        })?;
+        Ok(self.statement.sink)
     }
 
     fn push_constraint(&mut self, co: BilinearConstraint) -> zkinterface::Result<()> {
         self.constraints.constraints.push(co);
+        self.num_constraints += 1;
 
         if self.constraints.constraints.len() >= self.constraints_per_message {
-            let cs = mem::replace(&mut self.constraints, ConstraintSystem::default());
+            let cs = mem::take(&mut self.constraints);
             self.statement.push_constraints(cs)?;
+            self.num_constraint_messages += 1;
         }
         Ok(())
     }
 }
 
-impl<Scalar: PrimeField> bl::ConstraintSystem<Scalar> for ZkifCS<Scalar> {
+impl<Scalar: PrimeField, S: Sink> bl::ConstraintSystem<Scalar> for ZkifCS<Scalar, S> {
     type Root = Self;
 
     fn alloc<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
         where F: FnOnce() -> Result<Scalar, SynthesisError>,
               A: FnOnce() -> AR, AR: Into<String>
     {
+        if let Some(zkid) = self.call_input_ids.pop_front() {
+            return Ok(Variable::new_unchecked(Index::Aux(zkid as usize)));
+        }
+
         let zkid = self.statement.allocate_var();
 
         if self.target == Target::Prover {
@@ -135,7 +280,11 @@ impl<Scalar: PrimeField> bl::ConstraintSystem<Scalar> for ZkifCS<Scalar> {
         let b = b(LinearCombination::zero());
         let c = c(LinearCombination::zero());
 
-        let co = to_zkif_constraint(a, b, c);
+        let co = if self.raw_linear_combinations {
+            to_zkif_constraint_raw(a, b, c)
+        } else {
+            to_zkif_constraint(a, b, c)
+        };
         self.push_constraint(co).unwrap();
     }
 
@@ -212,26 +361,26 @@ fn test_zkif_cs() -> zkinterface::Result<()> {
             // First chunk of 4 constraints.
             ConstraintSystem {
                 constraints: vec![
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
                 ]
             },
             // Second chunk of 4 constraints.
             ConstraintSystem {
                 constraints: vec![
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 70, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![50, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![70, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
                 ]
             },
             // Final chunk of 2 constraints.
             ConstraintSystem {
                 constraints: vec![
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
-                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![1, 2, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![3, 0], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 90, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
+                    BilinearConstraint { linear_combination_a: Variables { variable_ids: vec![1], values: Some(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_b: Variables { variable_ids: vec![0, 1, 2], values: Some(vec![9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) }, linear_combination_c: Variables { variable_ids: vec![0, 3], values: Some(vec![90, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]) } },
                 ]
             }
         ],
@@ -249,4 +398,37 @@ fn test_zkif_cs() -> zkinterface::Result<()> {
     assert_eq!(messages, expected);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_zkif_cs_raw() -> zkinterface::Result<()> {
+    use std::fs::remove_dir_all;
+    use bellman::ConstraintSystem as BLCS;
+    use bls12_381::Scalar;
+    use zkinterface::Workspace;
+
+    let dir = Path::new("local/test_raw/");
+    let _ = remove_dir_all(dir);
+
+    let mut cs = ZkifCS::<Scalar>::new(dir, Target::Prover);
+    cs.raw_linear_combinations = true;
+
+    let one = ZkifCS::<Scalar>::one();
+    let x = cs.alloc_input(|| "x", || Ok(Scalar::from(3)))?;
+    let y = cs.alloc(|| "y", || Ok(Scalar::from(9)))?;
+
+    // The terms are written as given: a duplicate variable and a zero coefficient.
+    cs.enforce(
+        || "constraint",
+        |zero| zero + x,
+        |zero| zero + x + (Scalar::zero(), one),
+        |zero| zero + y + y - y,
+    );
+    cs.finish("test")?;
+
+    let messages = Workspace::from_dir(dir)?.read_all_messages();
+    let constraint = &messages.constraint_systems[0].constraints[0];
+    assert_eq!(constraint.linear_combination_b.variable_ids, vec![1, 0]);
+    assert_eq!(constraint.linear_combination_c.variable_ids, vec![2, 2, 2]);
+    Ok(())
+}
@@ -0,0 +1,404 @@
+//! Call ZoKrates programs as gadgets, through the zkInterface backend of ZoKrates.
+//!
+//! ZoKrates writes messages of an earlier version of zkInterface (`Circuit`, `R1CSConstraints`),
+//! which are converted into the current messages with read_legacy_messages.
+//! These messages do not declare a field; the responses of the gadgets are given the field of the call.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use num_bigint::BigUint;
+use zkinterface::{CircuitHeader, ConstraintSystem, Reader, Result, Variables, Witness};
+use zkinterface::consumers::reader::is_contiguous;
+use crate::gadget_registry::ZkifGadget;
+use crate::import::read_call;
+
+/// A ZoKrates program used as a gadget.
+///
+/// The arguments of its main function are the inputs of the call, and its return values are the outputs.
+/// ZoKrates requires the inputs to be numbered from 1.
+/// Each call runs in a new temporary directory, removed afterwards, so that calls are independent.
+///
+/// # Example
+/// ```no_run
+/// # use zkinterface_bellman::zokrates::ZokratesGadget;
+/// let mut gadget = ZokratesGadget::new("src/zokrates/demo.code");
+/// gadget.zokrates = "/opt/zokrates/target/release/zokrates".into();
+/// // call_gadget(cs, &[a, b], &gadget.exec_fn())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZokratesGadget {
+    /// The zokrates executable.
+    pub zokrates: PathBuf,
+    /// The source of the program, a .code file.
+    pub program: PathBuf,
+}
+
+impl ZokratesGadget {
+    /// Use the program with the `zokrates` executable from the PATH.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        ZokratesGadget {
+            zokrates: PathBuf::from("zokrates"),
+            program: program.into(),
+        }
+    }
+
+    /// The function to give to call_gadget.
+    pub fn exec_fn(&self) -> impl Fn(&[u8]) -> Result<Reader> + '_ {
+        move |call_msg| self.exec(call_msg)
+    }
+
+    pub fn exec(&self, call_msg: &[u8]) -> Result<Reader> {
+        let (call, command) = read_call(call_msg)?;
+
+        // Non-contiguous IDs are not supported by ZoKrates.
+        let input_ids = &call.instance_variables.variable_ids;
+        if !is_contiguous(1, input_ids) || call.free_variable_id != 1 + input_ids.len() as u64 {
+            return Err("ZoKrates requires inputs numbered from 1, and no other variables in the call.".into());
+        }
+
+        let program = self.program.canonicalize()
+            .map_err(|err| format!("Cannot find the program {}: {}", self.program.display(), err))?;
+        let zokrates = if self.zokrates.components().count() > 1 {
+            // A relative path must still work from the temporary directory.
+            self.zokrates.canonicalize()?
+        } else {
+            self.zokrates.clone()
+        };
+        let workspace = tempfile::tempdir()?;
+        let run = ZokratesRun { zokrates: &zokrates, workspace: workspace.path() };
+
+        run.exec(&["compile", "--input", program.to_str().ok_or("Invalid program path")?])?;
+
+        let mut reader = Reader::new();
+
+        if command.constraints_generation {
+            run.exec(&["setup", "--backend", "zkinterface", "-p", "r1cs.zkif"])?;
+            run.read_legacy_response(&mut reader, "r1cs.zkif", &call)?;
+            run.read_legacy_response(&mut reader, "circuit_r1cs.zkif", &call)?;
+        }
+
+        if command.witness_generation && call.instance_variables.values.is_some() {
+            // Give the input elements in decimal on the command line.
+            let mut args = vec!["compute-witness".to_string(), "--arguments".to_string()];
+            for input in call.instance_variables.get_variables() {
+                args.push(le_to_decimal(input.value));
+            }
+            run.exec(&args.iter().map(|a| a.as_str()).collect::<Vec<_>>())?;
+
+            run.exec(&["generate-proof", "--backend", "zkinterface", "-j", "witness.zkif"])?;
+            run.read_legacy_response(&mut reader, "witness.zkif", &call)?;
+            run.read_legacy_response(&mut reader, "circuit_witness.zkif", &call)?;
+        }
+
+        Ok(reader)
+    }
+}
+
+/// The commands of one call, in its own directory.
+struct ZokratesRun<'a> {
+    zokrates: &'a Path,
+    workspace: &'a Path,
+}
+
+impl<'a> ZokratesRun<'a> {
+    fn exec(&self, args: &[&str]) -> Result<()> {
+        let out = Command::new(self.zokrates)
+            .args(args)
+            .current_dir(self.workspace)
+            .output()
+            .map_err(|err| format!("Cannot run {}: {}", self.zokrates.display(), err))?;
+
+        if !out.status.success() {
+            return Err(format!("zokrates {} failed ({}): {}{}",
+                               args.join(" "), out.status,
+                               String::from_utf8_lossy(&out.stdout).trim(),
+                               String::from_utf8_lossy(&out.stderr).trim()).into());
+        }
+        Ok(())
+    }
+
+    fn read_legacy_response(&self, reader: &mut Reader, name: &str, call: &CircuitHeader) -> Result<()> {
+        read_legacy_response(reader, self.workspace.join(name), call)
+    }
+}
+
+impl ZkifGadget for ZokratesGadget {
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        self.exec(call_msg)
+    }
+}
+
+/// A stand-in for ZoKrates that answers from recorded messages, to test without ZoKrates installed.
+///
+/// The directory contains the call as written by ZoKrates tooling (`call.zkif`), and the responses
+/// `r1cs.zkif`, `circuit_r1cs.zkif`, `witness.zkif`, `circuit_witness.zkif`, all in the earlier schema.
+/// A call must have the same input ids and free_variable_id as the recorded call, and the same values if any.
+///
+/// # Example
+/// ```
+/// # use zkinterface_bellman::zokrates::ZokratesReplay;
+/// let gadget = ZokratesReplay::new("src/zokrates/messages");
+/// // call_gadget(cs, &[a, b], &gadget.exec_fn())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZokratesReplay {
+    pub messages: PathBuf,
+}
+
+impl ZokratesReplay {
+    pub fn new(messages: impl Into<PathBuf>) -> Self {
+        ZokratesReplay { messages: messages.into() }
+    }
+
+    /// The function to give to call_gadget.
+    pub fn exec_fn(&self) -> impl Fn(&[u8]) -> Result<Reader> + '_ {
+        move |call_msg| self.exec(call_msg)
+    }
+
+    pub fn exec(&self, call_msg: &[u8]) -> Result<Reader> {
+        let (call, command) = read_call(call_msg)?;
+
+        let mut recorded = Reader::new();
+        read_legacy_file(&mut recorded, self.messages.join("call.zkif"))?;
+        let recorded = CircuitHeader::from(recorded.last_header().ok_or("No recorded call.")?);
+
+        if call.instance_variables.variable_ids != recorded.instance_variables.variable_ids
+            || call.free_variable_id != recorded.free_variable_id {
+            return Err(format!("The call (inputs {:?}, free variable {}) does not match the recording (inputs {:?}, free variable {}).",
+                               call.instance_variables.variable_ids, call.free_variable_id,
+                               recorded.instance_variables.variable_ids, recorded.free_variable_id).into());
+        }
+
+        let witness_generation = command.witness_generation && call.instance_variables.values.is_some();
+        if witness_generation {
+            let values: Vec<String> = call.instance_variables.get_variables().iter().map(|v| le_to_decimal(v.value)).collect();
+            let expected: Vec<String> = recorded.instance_variables.get_variables().iter().map(|v| le_to_decimal(v.value)).collect();
+            if values != expected {
+                return Err(format!("The input values {:?} do not match the recording {:?}.", values, expected).into());
+            }
+        }
+
+        let mut reader = Reader::new();
+        if command.constraints_generation {
+            read_legacy_response(&mut reader, self.messages.join("r1cs.zkif"), &call)?;
+            read_legacy_response(&mut reader, self.messages.join("circuit_r1cs.zkif"), &call)?;
+        }
+        if witness_generation {
+            read_legacy_response(&mut reader, self.messages.join("witness.zkif"), &call)?;
+            read_legacy_response(&mut reader, self.messages.join("circuit_witness.zkif"), &call)?;
+        }
+        Ok(reader)
+    }
+}
+
+impl ZkifGadget for ZokratesReplay {
+    fn call(&self, call_msg: &[u8]) -> Result<Reader> {
+        self.exec(call_msg)
+    }
+}
+
+/// Read a file written by ZoKrates, and convert its messages.
+pub fn read_legacy_file(reader: &mut Reader, path: impl AsRef<Path>) -> Result<()> {
+    reader.push_message(convert_legacy_messages(read_bytes(path.as_ref())?, None)?)
+}
+
+/// Read a response written by ZoKrates to a call, in the field of the call.
+fn read_legacy_response(reader: &mut Reader, path: impl AsRef<Path>, call: &CircuitHeader) -> Result<()> {
+    reader.push_message(convert_legacy_messages(read_bytes(path.as_ref())?, call.field_maximum.clone())?)
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    File::open(path)
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Convert messages of the earlier zkInterface schema used by ZoKrates into current messages.
+///
+/// A `Circuit` message becomes a CircuitHeader, with its `connections` as instance variables,
+/// and without field_maximum nor configuration. `R1CSConstraints` and `Witness` have the same layout as
+/// ConstraintSystem and Witness.
+pub fn read_legacy_messages(buf: Vec<u8>) -> Result<Vec<u8>> {
+    convert_legacy_messages(buf, None)
+}
+
+fn convert_legacy_messages(buf: Vec<u8>, field_maximum: Option<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut legacy = Reader::new();
+    legacy.push_message(buf)?;
+
+    let mut converted = vec![];
+    for msg in &legacy {
+        if let Some(circuit) = msg.message_as_circuit_header() {
+            // Only the fields common to both schemas are read.
+            CircuitHeader {
+                instance_variables: circuit.instance_variables().map(Variables::from).unwrap_or_default(),
+                free_variable_id: circuit.free_variable_id(),
+                field_maximum: field_maximum.clone(),
+                configuration: None,
+            }.write_into(&mut converted)?;
+        } else if let Some(constraints) = msg.message_as_constraint_system() {
+            ConstraintSystem::from(constraints).write_into(&mut converted)?;
+        } else if let Some(witness) = msg.message_as_witness() {
+            Witness::from(witness).write_into(&mut converted)?;
+        }
+    }
+    Ok(converted)
+}
+
+/// Convert zkInterface little-endian bytes to ZoKrates decimal.
+fn le_to_decimal(bytes_le: &[u8]) -> String {
+    BigUint::from_bytes_le(bytes_le).to_str_radix(10)
+}
+
+
+#[test]
+fn test_read_legacy_messages() -> Result<()> {
+    use zkinterface::Messages;
+
+    let dir = Path::new("src/zokrates/messages");
+    let mut reader = Reader::new();
+    for name in &["r1cs.zkif", "circuit_r1cs.zkif", "witness.zkif", "circuit_witness.zkif"] {
+        read_legacy_file(&mut reader, dir.join(name))?;
+    }
+    let messages = Messages::from(&reader);
+
+    assert_eq!(messages.circuit_headers.len(), 2);
+    let header = &messages.circuit_headers[1];
+    assert_eq!(header.instance_variables.variable_ids, vec![0, 1, 2, 3]);
+    assert_eq!(header.instance_variables.values, Some(vec![1, 5, 0, 25]));
+    assert_eq!(header.free_variable_id, 6);
+    assert_eq!(messages.constraint_systems[0].constraints.len(), 3);
+    assert_eq!(messages.witnesses[0].assigned_variables.variable_ids, vec![4, 5]);
+
+    assert_eq!(le_to_decimal(&[0, 1]), "256");
+    Ok(())
+}
+
+#[test]
+fn test_zokrates_gadget_input_ids() -> Result<()> {
+    use bellman::ConstraintSystem;
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::{call_gadget, call_gadget_with_options, GadgetCallOptions, InputIds};
+
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let inputs = vec![AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Scalar::from(3)))?];
+
+    let mut gadget = ZokratesGadget::new("src/zokrates/demo.code");
+    gadget.zokrates = PathBuf::from("no_zokrates_here");
+    let options = GadgetCallOptions {
+        input_ids: InputIds::Explicit(vec![7]),
+        ..GadgetCallOptions::default()
+    };
+    match call_gadget_with_options(&mut cs, &inputs, &gadget.exec_fn(), &options) {
+        Err(err) => assert!(err.to_string().contains("numbered from 1")),
+        Ok(_) => panic!("Should refuse non-contiguous inputs"),
+    }
+
+    match call_gadget(&mut cs, &inputs, &gadget.exec_fn()) {
+        Err(err) => assert!(err.to_string().contains("Cannot run no_zokrates_here")),
+        Ok(_) => panic!("Should fail without zokrates"),
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_zokrates_concurrent_calls() -> Result<()> {
+    use std::fs::{create_dir_all, read_dir, set_permissions, write, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+    use bellman::ConstraintSystem;
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::call_gadget;
+
+    // A fake zokrates that answers the recorded a=5, b=0 call, and fails on stale or missing files.
+    let dir = PathBuf::from("local/test_zokrates_concurrent_calls");
+    create_dir_all(&dir)?;
+    let fixtures = Path::new("src/zokrates/messages").canonicalize()?;
+    let script = format!(r#"#!/bin/sh
+set -e
+F={}
+case "$1" in
+  compile) test ! -e out && test -f "$3" && touch out ;;
+  setup) test -f out && test ! -e r1cs.zkif && cp $F/r1cs.zkif $F/circuit_r1cs.zkif . ;;
+  compute-witness) test -f out && test "$3 $4" = "5 0" && echo "$3 $4" > witness ;;
+  generate-proof) sleep 0.1 && test -f witness && cp $F/witness.zkif $F/circuit_witness.zkif . ;;
+esac
+"#, fixtures.display());
+    let zokrates = dir.join("zokrates");
+    write(&zokrates, script)?;
+    set_permissions(&zokrates, Permissions::from_mode(0o755))?;
+
+    let mut gadget = ZokratesGadget::new("src/zokrates/demo.code");
+    gadget.zokrates = zokrates;
+
+    let threads: Vec<_> = (0..4).map(|_| {
+        let gadget = gadget.clone();
+        thread::spawn(move || {
+            let mut cs = TestConstraintSystem::<Scalar>::new();
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Scalar::from(5))).unwrap();
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Scalar::from(0))).unwrap();
+            let outputs = call_gadget(&mut cs, &[a, b], &gadget.exec_fn()).unwrap();
+            assert!(cs.is_satisfied());
+            outputs[0].get_value()
+        })
+    }).collect();
+
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), Some(Scalar::from(25)));
+    }
+
+    // Nothing is written next to the program or the executable.
+    assert_eq!(read_dir(&dir)?.count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_zokrates_replay() -> Result<()> {
+    use bellman::{ConstraintSystem, SynthesisError};
+    use bellman::gadgets::{num::AllocatedNum, test::TestConstraintSystem};
+    use bls12_381::Scalar;
+    use crate::import::call_gadget;
+    use crate::zkif_cs::{ZkifCS, Target};
+
+    let gadget = ZokratesReplay::new("src/zokrates/messages");
+
+    // a² + b² with a = 5, b = 0.
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Scalar::from(5)))?;
+    let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Scalar::from(0)))?;
+    let outputs = call_gadget(&mut cs.namespace(|| "zokrates"), &[a.clone(), b.clone()], &gadget.exec_fn())?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].get_value(), Some(Scalar::from(25)));
+    assert!(cs.is_satisfied());
+    assert_eq!(cs.num_constraints(), 3);
+
+    // Setup, without values.
+    let mut setup_cs = ZkifCS::<Scalar>::new("local/test_zokrates_replay", Target::Preprocessing);
+    let inputs = vec![
+        AllocatedNum::alloc(setup_cs.namespace(|| "a"), || Err(SynthesisError::AssignmentMissing))?,
+        AllocatedNum::alloc(setup_cs.namespace(|| "b"), || Err(SynthesisError::AssignmentMissing))?,
+    ];
+    let outputs = call_gadget(&mut setup_cs, &inputs, &gadget.exec_fn())?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(setup_cs.num_constraints(), 3);
+
+    // Other inputs are not recorded.
+    let c = AllocatedNum::alloc(cs.namespace(|| "c"), || Ok(Scalar::from(3)))?;
+    let d = AllocatedNum::alloc(cs.namespace(|| "d"), || Ok(Scalar::from(4)))?;
+    match call_gadget(&mut cs.namespace(|| "other values"), &[c.clone(), d], &gadget.exec_fn()) {
+        Err(err) => assert!(err.to_string().contains("do not match the recording")),
+        Ok(_) => panic!("Should refuse other input values"),
+    }
+    match call_gadget(&mut cs.namespace(|| "other inputs"), &[c], &gadget.exec_fn()) {
+        Err(err) => assert!(err.to_string().contains("does not match the recording")),
+        Ok(_) => panic!("Should refuse other inputs"),
+    }
+    Ok(())
+}